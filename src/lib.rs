@@ -0,0 +1,218 @@
+use bevy::{prelude::*, window::WindowResizeConstraints};
+use bevy_asset_loader::*;
+use bevy_ecs_ldtk::prelude::*;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+use iyes_progress::prelude::*;
+use bevy_embedded_assets::EmbeddedAssetPlugin;
+// use bevy_sprite_animation::prelude::*;
+
+use plugins::{
+    animation_graph::{AnimationGraph, AnimationGraphPlugin},
+    damage::DamagePlugin,
+    debug::DebugPlugin,
+    display::{DisplayPlugin, ViewScaling},
+    enemy::{EnemyBundle, EnemyPlugin},
+    headless::HeadlessLevelPlugin,
+    hud::HudPlugin,
+    input::{PlayerInputPlugin, ReplayMode},
+    laser::LaserPlugin,
+    menu::{KeepOnRestart, MenuPlugin},
+    particles::ParticlePlugin,
+    player::{PlayerBundle, PlayerPlugin},
+    projectile::ProjectilePlugin,
+    rng::GameRng,
+    save::SavePlugin,
+    score::{HighScores, ScorePlugin},
+    settings::{Settings, SettingsPlugin},
+    sound::SoundPlugin,
+    sprite_animation::SpriteAnimationPlugin,
+    status::StatusPlugin,
+    tilemap::{CameraFocus, DestructibleBundle, HazardBundle, PitBundle, TileMapPlugin, WallBundle, WaterBundle},
+    timestep::{GameTick, TimestepPlugin},
+};
+
+pub mod plugins;
+
+const HEIGHT: f32 = 640.;
+/// Aspect ratio of the game view, windows of any other shape get letterboxed or extended
+const RESOLUTION: f32 = 16. / 9.;
+
+#[derive(Component)]
+pub struct MainCamera;
+
+/// Builds the game with a window and renderer, ready to `run()`
+pub fn app(replay_mode: ReplayMode, rng: GameRng) -> App {
+    let mut app = App::new();
+    app.insert_resource(replay_mode).insert_resource(rng);
+    app.add_loopless_state(GameState::AssetLoading);
+
+    AssetLoader::new(GameState::AssetLoading)
+        // https://github.com/NiklasEi/bevy_asset_loader/issues/54
+        .continue_to_state(GameState::MainMenu)
+        .with_collection::<MyAssets>()
+        .build(&mut app);
+
+    let settings = Settings::load();
+
+    // .add_system_set(SystemSet::on_update(GameState::Playing).with_system(systems::pause_physics_during_load))
+    app.insert_resource(WindowDescriptor {
+        height: HEIGHT,
+        width: HEIGHT * RESOLUTION,
+        position: Some(Vec2::new(200., 20.)),
+        title: "T5hm".into(),
+        present_mode: settings.present_mode(),
+        mode: settings.window_mode(),
+        #[cfg(target_arch = "wasm32")]
+        canvas: Some("#bevy-canvas".to_string()),
+        resizable: true,
+        resize_constraints: WindowResizeConstraints {
+            min_width: HEIGHT * RESOLUTION / 4.,
+            min_height: HEIGHT / 4.,
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert_resource(settings.view_scaling)
+    .insert_resource(settings)
+    .insert_resource(HighScores::load())
+    .add_plugins_with(DefaultPlugins, |group| {
+        group.add_before::<bevy::asset::AssetPlugin, _>(EmbeddedAssetPlugin)
+    })
+    .add_plugin(ProgressPlugin::new(GameState::AssetLoading))
+    .add_plugin(LdtkPlugin)
+    // the physics systems are run on the fixed timestep by `TimestepPlugin`
+    .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(50.0).with_default_system_setup(false));
+    if cfg!(debug_assertions) {
+        app.add_plugin(RapierDebugRenderPlugin::default());
+    }
+    app.add_plugin(DebugPlugin)
+        .add_plugin(SoundPlugin)
+        .add_plugin(HudPlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(AnimationGraphPlugin)
+        .insert_resource(LdtkSettings {
+            level_spawn_behavior: LevelSpawnBehavior::UseWorldTranslation {
+                load_level_neighbors: true,
+            },
+            set_clear_color: SetClearColor::FromLevelBackground,
+            int_grid_rendering: IntGridRendering::Colorful,
+            level_background: LevelBackground::Rendered,
+            ..Default::default()
+        })
+        .insert_resource(ClearColor(Color::GRAY))
+        .insert_resource(LevelSelection::Uid(0))
+        .add_startup_system(spawn_ui_camera)
+        .add_enter_system(GameState::Playing, setup)
+        .register_ldtk_int_cell::<WallBundle>(1)
+        .register_ldtk_int_cell::<HazardBundle>(2)
+        .register_ldtk_int_cell::<WaterBundle>(3)
+        .register_ldtk_int_cell::<PitBundle>(4)
+        .register_ldtk_int_cell::<DestructibleBundle>(5)
+        .register_ldtk_entity::<PlayerBundle>("Player")
+        .register_ldtk_entity::<EnemyBundle>("Enemy");
+    add_gameplay_plugins(&mut app);
+    app
+}
+
+/// Builds the game without a window or renderer, for tests
+///
+/// Every `update()` runs exactly one gameplay tick, and input comes from `replay_mode`.
+/// The map is read straight from `thm_map.ldtk` since the LDtk plugin needs a renderer.
+pub fn headless_app(replay_mode: ReplayMode, rng: GameRng) -> App {
+    let mut app = App::new();
+    app.insert_resource(replay_mode)
+        .insert_resource(rng)
+        .insert_resource(GameTick {
+            manual: true,
+            ..Default::default()
+        })
+        .add_plugins(MinimalPlugins)
+        .add_plugin(bevy::transform::TransformPlugin)
+        .add_plugin(bevy::hierarchy::HierarchyPlugin)
+        .add_plugin(bevy::asset::AssetPlugin)
+        .add_plugin(bevy::input::InputPlugin)
+        .add_plugin(bevy::window::WindowPlugin::default())
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(50.0).with_default_system_setup(false))
+        .add_loopless_state(GameState::AssetLoading)
+        // there's nothing to load, so go straight to playing
+        .add_startup_system(|mut commands: Commands| {
+            commands.insert_resource(NextState(GameState::Playing))
+        })
+        .insert_resource(MyAssets::default())
+        .insert_resource(LevelSelection::Uid(0))
+        .add_plugin(HeadlessLevelPlugin);
+    add_gameplay_plugins(&mut app);
+    app
+}
+
+fn add_gameplay_plugins(app: &mut App) {
+    app.add_plugin(TimestepPlugin)
+        .add_plugin(DisplayPlugin)
+        .add_plugin(PlayerInputPlugin)
+        .add_plugin(TileMapPlugin)
+        .add_plugin(PlayerPlugin)
+        .add_plugin(EnemyPlugin)
+        .add_plugin(DamagePlugin)
+        .add_plugin(StatusPlugin)
+        .add_plugin(ScorePlugin)
+        .add_plugin(SettingsPlugin)
+        .add_plugin(SpriteAnimationPlugin)
+        .add_plugin(ParticlePlugin)
+        .add_plugin(ProjectilePlugin)
+        .add_plugin(LaserPlugin);
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum GameState {
+    AssetLoading,
+    MainMenu,
+    Playing,
+    GameOver,
+}
+
+#[derive(AssetCollection, Default)]
+pub struct MyAssets {
+    #[asset(texture_atlas(tile_size_x = 16., tile_size_y = 16., columns = 5, rows = 1, padding_x = 2., padding_y = 2.))]
+    #[asset(path = "aseprite_animation.png")]
+    slash: Handle<TextureAtlas>,
+    #[asset(path = "Player.png")]
+    pub player: Handle<Image>,
+    #[asset(texture_atlas(tile_size_x = 16., tile_size_y = 16., columns = 6, rows = 5))]
+    #[asset(path = "player_sheet.png")]
+    pub player_sheet: Handle<TextureAtlas>,
+    #[asset(texture_atlas(tile_size_x = 16., tile_size_y = 16., columns = 1, rows = 1))]
+    #[asset(path = "background.png")]
+    pub enemy_sheet: Handle<TextureAtlas>,
+    #[asset(path = "white.png")]
+    pub wall: Handle<Image>,
+    #[asset(path = "arrow.png")]
+    pub arrow: Handle<Image>,
+    #[asset(path = "thm_map.ldtk")]
+    pub map: Handle<LdtkAsset>,
+    #[asset(path = "fonts/DejaVuSansMono-Bold.ttf")]
+    pub font: Handle<Font>,
+    #[asset(path = "player.nodetree")]
+    pub player_animation: Handle<AnimationGraph>,
+}
+
+/// For the HUD and menus, kept for every run
+fn spawn_ui_camera(mut commands: Commands) {
+    commands
+        .spawn_bundle(UiCameraBundle::default())
+        .insert(KeepOnRestart);
+}
+
+fn setup(mut commands: Commands, my_assets: Res<MyAssets>) {
+    let camera = OrthographicCameraBundle::new_2d();
+    commands
+        .spawn_bundle(camera)
+        .insert(MainCamera)
+        .insert(CameraFocus::default());
+
+    commands.spawn_bundle(LdtkWorldBundle {
+        ldtk_handle: my_assets.map.clone(),
+        ..Default::default()
+    });
+}
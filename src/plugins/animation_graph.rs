@@ -0,0 +1,583 @@
+use std::{fmt, iter::Peekable, str::CharIndices};
+
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap},
+};
+use iyes_loopless::prelude::*;
+
+use crate::{GameState, MyAssets};
+
+use super::player::{PlayerBody, PlayerState};
+
+/// Seconds each frame of an `IndexNode` is shown for
+const FRAME_TIME: f32 = 0.1;
+
+/// Ids the node editor gave the nodes it saved without one, by name
+///
+/// Other nodes refer to them by these, which can't be worked out from the name alone.
+const EDITOR_IDS: &[(&str, u64)] = &[("Player_Melee", 0x8EA5_2888_68FD_8F3A)];
+
+/// Identifies a node in a node tree, written as a hex string like `NodeID("0x1F")`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(pub u64);
+
+impl NodeId {
+    fn parse(hex: &str) -> Option<Self> {
+        let digits = hex.strip_prefix("0x").or_else(|| hex.strip_prefix("0X"))?;
+        u64::from_str_radix(digits, 16).ok().map(Self)
+    }
+
+    /// Id for a node written without one, the one other nodes refer to it by
+    fn from_name(name: &str) -> Self {
+        if let Some((_, id)) = EDITOR_IDS.iter().find(|(editor_name, _)| *editor_name == name) {
+            return Self(*id);
+        }
+        // FNV-1a, so the same name gets the same id every run
+        Self(name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        }))
+    }
+}
+
+/// Picks one of its cases by the value of an attribute, like `PlayerState`
+#[derive(Debug, Clone)]
+pub struct MatchNode {
+    pub name: String,
+    pub attribute: String,
+    /// followed when no case matches
+    pub default: NodeId,
+    pub cases: Vec<(String, NodeId)>,
+}
+
+/// Shows one of its frames, picked by an index that counts up over time
+#[derive(Debug, Clone)]
+pub struct IndexNode {
+    pub name: String,
+    /// paths relative to the assets folder
+    pub frames: Vec<String>,
+    pub is_loop: bool,
+    pub index: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Match(MatchNode),
+    Index(IndexNode),
+}
+
+/// What a node tree reads from an entity to pick its frame
+pub trait AnimationAttributes {
+    /// Value of a `MatchNode`'s attribute, compared with the names of its cases
+    fn attribute(&self, name: &str) -> Option<String>;
+    /// Unbounded frame count for an `IndexNode`'s index, the node wraps or clamps it
+    fn index(&self, name: &str) -> usize;
+}
+
+/// The animation graph described by a `.nodetree` file
+#[derive(Debug, Clone)]
+pub struct NodeTree {
+    /// the first node in the file
+    pub root: NodeId,
+    pub nodes: HashMap<NodeId, Node>,
+}
+
+impl NodeTree {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(source);
+        let items = parser.list_items('[', ']')?;
+        parser.end()?;
+
+        let mut root = None;
+        let mut nodes = HashMap::default();
+        for item in items {
+            let (id, node) = match item {
+                Value::Keyed(key, node) => (Some(node_id(&key)?), node_from_value(*node)?),
+                value => (None, node_from_value(value)?),
+            };
+            let id = id.unwrap_or_else(|| {
+                NodeId::from_name(match &node {
+                    Node::Match(node) => &node.name,
+                    Node::Index(node) => &node.name,
+                })
+            });
+            root.get_or_insert(id);
+            nodes.insert(id, node);
+        }
+
+        Ok(Self {
+            root: root.ok_or_else(|| ParseError::new(0, "the tree has no nodes"))?,
+            nodes,
+        })
+    }
+
+    /// Follows the tree from the root to the frame to show, if it leads to one
+    pub fn frame(&self, attributes: &impl AnimationAttributes) -> Option<&str> {
+        let mut id = self.root;
+        // a tree pointing back at itself would otherwise never finish
+        for _ in 0..self.nodes.len() {
+            match self.nodes.get(&id)? {
+                Node::Match(node) => {
+                    let value = attributes.attribute(&node.attribute);
+                    id = node
+                        .cases
+                        .iter()
+                        .find(|(case, _)| Some(case) == value.as_ref())
+                        .map_or(node.default, |(_, case_id)| *case_id);
+                }
+                Node::Index(node) => {
+                    if node.frames.is_empty() {
+                        return None;
+                    }
+                    let index = attributes.index(&node.index);
+                    let index = if node.is_loop {
+                        index % node.frames.len()
+                    } else {
+                        index.min(node.frames.len() - 1)
+                    };
+                    return Some(&node.frames[index]);
+                }
+            }
+        }
+        None
+    }
+
+    fn frame_paths(&self) -> impl Iterator<Item = &String> {
+        self.nodes
+            .values()
+            .filter_map(|node| match node {
+                Node::Index(node) => Some(node.frames.iter()),
+                Node::Match(_) => None,
+            })
+            .flatten()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// byte offset into the file
+    pub offset: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new(offset: usize, message: impl Into<String>) -> Self {
+        Self {
+            offset,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The RON-like values a `.nodetree` file is made of
+#[derive(Debug, Clone)]
+enum Value {
+    Str(String),
+    /// identifiers, booleans and unquoted paths
+    Word(String),
+    /// `Name(values)`
+    Call(String, Vec<Value>),
+    /// `Name<Generic>(field: value, ...)`
+    Struct(String, Vec<(String, Value)>),
+    /// `(values)`
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+    /// `key: value`, how nodes are given their ids
+    Keyed(Box<Value>, Box<Value>),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().peekable(),
+        }
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.source.len(), |(i, _)| *i)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while let Some((_, c)) = self.chars.peek() {
+            if !c.is_whitespace() {
+                return Some(*c);
+            }
+            self.chars.next();
+        }
+        None
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        if self.eat(expected) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", expected)))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error("expected the end of the file")),
+        }
+    }
+
+    fn error(&mut self, message: impl Into<String>) -> ParseError {
+        ParseError::new(self.offset(), message)
+    }
+
+    /// Values separated by commas, with an optional trailing comma
+    fn list_items(&mut self, open: char, close: char) -> Result<Vec<Value>, ParseError> {
+        self.expect(open)?;
+        let mut items = Vec::new();
+        while !self.eat(close) {
+            items.push(self.keyed_value()?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    fn keyed_value(&mut self) -> Result<Value, ParseError> {
+        let value = self.value()?;
+        if self.eat(':') {
+            Ok(Value::Keyed(Box::new(value), Box::new(self.value()?)))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.string().map(Value::Str),
+            Some('[') => self.list_items('[', ']').map(Value::List),
+            Some('(') => self.list_items('(', ')').map(Value::Tuple),
+            Some(_) => {
+                let word = self.word()?;
+                // generics only say what the node matches on, which the attribute already does
+                if self.eat('<') {
+                    self.word()?;
+                    self.expect('>')?;
+                }
+                if self.peek() != Some('(') {
+                    return Ok(Value::Word(word));
+                }
+                let items = self.list_items('(', ')')?;
+                if items.iter().all(|item| matches!(item, Value::Keyed(..))) && !items.is_empty() {
+                    let mut fields = Vec::new();
+                    for item in items {
+                        if let Value::Keyed(key, value) = item {
+                            match *key {
+                                Value::Word(key) => fields.push((key, *value)),
+                                _ => return Err(self.error("expected a field name")),
+                            }
+                        }
+                    }
+                    Ok(Value::Struct(word, fields))
+                } else {
+                    Ok(Value::Call(word, items))
+                }
+            }
+            None => Err(self.error("unexpected end of file")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(string),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) => string.push(c),
+                    None => break,
+                },
+                Some((_, c)) => string.push(c),
+                None => break,
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    /// Anything up to whitespace or punctuation, including paths like `slash/frame1.png`
+    fn word(&mut self) -> Result<String, ParseError> {
+        self.peek();
+        let start = self.offset();
+        while let Some((_, c)) = self.chars.peek() {
+            if c.is_whitespace() || "[](){}<>:,\"".contains(*c) {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+        if start == end {
+            return Err(self.error("expected a name"));
+        }
+        Ok(self.source[start..end].to_string())
+    }
+}
+
+fn node_id(value: &Value) -> Result<NodeId, ParseError> {
+    match value {
+        // `NodeID("0x...")` as a node's key, and `("0x...")` when referring to one
+        Value::Call(name, args) if name == "NodeID" => match args.as_slice() {
+            [Value::Str(hex)] => NodeId::parse(hex),
+            _ => None,
+        },
+        Value::Tuple(args) => match args.as_slice() {
+            [Value::Str(hex)] => NodeId::parse(hex),
+            _ => None,
+        },
+        _ => None,
+    }
+    .ok_or_else(|| ParseError::new(0, format!("expected a node id, found {:?}", value)))
+}
+
+fn node_from_value(value: Value) -> Result<Node, ParseError> {
+    let (kind, mut fields) = match value {
+        Value::Struct(kind, fields) => (kind, fields.into_iter().collect::<HashMap<_, _>>()),
+        value => {
+            return Err(ParseError::new(
+                0,
+                format!("expected a node, found {:?}", value),
+            ))
+        }
+    };
+    let mut field = |name: &str| {
+        fields
+            .remove(name)
+            .ok_or_else(|| ParseError::new(0, format!("{} is missing {}", kind, name)))
+    };
+    let string = |value: Value| match value {
+        Value::Str(string) | Value::Word(string) => Ok(string),
+        // `AttributeName("...")` and `IndexName("...")`
+        Value::Call(_, mut args) if args.len() == 1 => match args.remove(0) {
+            Value::Str(string) => Ok(string),
+            value => Err(ParseError::new(
+                0,
+                format!("expected a name, found {:?}", value),
+            )),
+        },
+        value => Err(ParseError::new(
+            0,
+            format!("expected a name, found {:?}", value),
+        )),
+    };
+
+    match kind.as_str() {
+        "MatchNode" => {
+            let cases = match field("pairs")? {
+                Value::List(pairs) => pairs
+                    .into_iter()
+                    .map(|pair| match pair {
+                        Value::Tuple(mut pair) if pair.len() == 2 => {
+                            let id = node_id(&pair.remove(1))?;
+                            Ok((string(pair.remove(0))?, id))
+                        }
+                        pair => Err(ParseError::new(
+                            0,
+                            format!("expected a case, found {:?}", pair),
+                        )),
+                    })
+                    .collect::<Result<_, _>>()?,
+                value => {
+                    return Err(ParseError::new(
+                        0,
+                        format!("expected cases, found {:?}", value),
+                    ))
+                }
+            };
+            Ok(Node::Match(MatchNode {
+                name: string(field("name")?)?,
+                attribute: string(field("check")?)?,
+                default: node_id(&field("default")?)?,
+                cases,
+            }))
+        }
+        "IndexNode" => {
+            let frames = match field("frames")? {
+                Value::List(frames) => frames.into_iter().map(string).collect::<Result<_, _>>()?,
+                value => {
+                    return Err(ParseError::new(
+                        0,
+                        format!("expected frames, found {:?}", value),
+                    ))
+                }
+            };
+            Ok(Node::Index(IndexNode {
+                name: string(field("name")?)?,
+                frames,
+                is_loop: string(field("is_loop")?)? == "true",
+                index: string(field("index")?)?,
+            }))
+        }
+        kind => Err(ParseError::new(0, format!("unknown node type {}", kind))),
+    }
+}
+
+/// A node tree with its frames loaded
+#[derive(TypeUuid)]
+#[uuid = "9b1d2f64-6c3a-4e0b-8f7e-51a2c4d3e9b7"]
+pub struct AnimationGraph {
+    pub tree: NodeTree,
+    frames: HashMap<String, Handle<Image>>,
+}
+
+impl AnimationGraph {
+    pub fn frame(&self, attributes: &impl AnimationAttributes) -> Option<&Handle<Image>> {
+        self.tree
+            .frame(attributes)
+            .and_then(|path| self.frames.get(path))
+    }
+}
+
+#[derive(Default)]
+pub struct AnimationGraphLoader;
+
+impl AssetLoader for AnimationGraphLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let tree = NodeTree::parse(std::str::from_utf8(bytes)?)?;
+            let paths: Vec<AssetPath> = tree
+                .frame_paths()
+                .map(|path| AssetPath::from(path.as_str()).to_owned())
+                .collect();
+            let frames = tree
+                .frame_paths()
+                .map(|path| (path.clone(), load_context.get_handle(path.as_str())))
+                .collect();
+            load_context.set_default_asset(
+                LoadedAsset::new(AnimationGraph { tree, frames }).with_dependencies(paths),
+            );
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["nodetree"]
+    }
+}
+
+/// Seconds the player has been in their current `PlayerState`
+///
+/// Counted in ticks by `PlayerPlugin`, the same clock the slash it shows runs on.
+#[derive(Component, Default)]
+pub struct AnimationTime(pub f32);
+
+struct PlayerAttributes<'a> {
+    state: &'a PlayerState,
+    time: f32,
+}
+
+impl AnimationAttributes for PlayerAttributes<'_> {
+    fn attribute(&self, name: &str) -> Option<String> {
+        match name {
+            "PlayerState" => Some(format!("{:?}", self.state)),
+            _ => None,
+        }
+    }
+
+    fn index(&self, _name: &str) -> usize {
+        (self.time / FRAME_TIME) as usize
+    }
+}
+
+pub struct AnimationGraphPlugin;
+
+impl Plugin for AnimationGraphPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<AnimationGraph>()
+            .init_asset_loader::<AnimationGraphLoader>()
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::animate_player)
+                    .into(),
+            );
+    }
+}
+
+impl AnimationGraphPlugin {
+    fn animate_player(
+        mut player_query: Query<
+            (
+                &PlayerState,
+                &AnimationTime,
+                &mut Handle<Image>,
+                &mut Visibility,
+            ),
+            Without<PlayerBody>,
+        >,
+        mut body_query: Query<&mut Visibility, With<PlayerBody>>,
+        graphs: Res<Assets<AnimationGraph>>,
+        my_assets: Res<MyAssets>,
+    ) {
+        let graph = graphs.get(&my_assets.player_animation);
+        for (state, animation_time, mut texture, mut visibility) in player_query.iter_mut() {
+            // states the tree has no frames for show the directional `PlayerBody` instead
+            if !state.in_nodetree() {
+                Self::show_body(&mut visibility, &mut body_query, true);
+                continue;
+            }
+            let attributes = PlayerAttributes {
+                state,
+                time: animation_time.0,
+            };
+            // the graph may still be loading
+            let frame = graph.and_then(|graph| graph.frame(&attributes));
+            if let Some(frame) = frame {
+                if *texture != *frame {
+                    *texture = frame.clone();
+                }
+            }
+            Self::show_body(&mut visibility, &mut body_query, frame.is_none());
+        }
+    }
+
+    /// Shows either the `PlayerBody` or the player's own sprite, the other is hidden
+    fn show_body(
+        visibility: &mut Visibility,
+        body_query: &mut Query<&mut Visibility, With<PlayerBody>>,
+        show: bool,
+    ) {
+        if visibility.is_visible == show {
+            visibility.is_visible = !show;
+        }
+        for mut body_visibility in body_query.iter_mut() {
+            if body_visibility.is_visible != show {
+                body_visibility.is_visible = show;
+            }
+        }
+    }
+}
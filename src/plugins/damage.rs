@@ -0,0 +1,184 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{GameState, MyAssets};
+
+use super::status::StatusKind;
+
+/// How long a damage number stays on screen
+const NUMBER_LIFETIME: f32 = 0.8;
+/// Continuous damage hitting the same target within this window is added to the same number
+const AGGREGATE_WINDOW: f32 = 0.2;
+const FLOAT_SPEED: f32 = 30.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DamageKind {
+    Bullet,
+    Laser,
+    Slash,
+    Burning,
+    Poison,
+    /// hazard tiles and pits
+    Hazard,
+}
+
+impl DamageKind {
+    fn color(&self) -> Color {
+        match self {
+            DamageKind::Bullet => Color::WHITE,
+            DamageKind::Laser => Color::rgb(1., 0.45, 0.2),
+            DamageKind::Slash => Color::rgb(0.55, 0.8, 1.),
+            DamageKind::Burning => Color::ORANGE,
+            DamageKind::Poison => Color::LIME_GREEN,
+            DamageKind::Hazard => Color::CRIMSON,
+        }
+    }
+
+    /// Whether hits of this kind arrive every frame and should share a single number
+    fn is_continuous(&self) -> bool {
+        matches!(self, DamageKind::Laser | DamageKind::Hazard)
+    }
+}
+
+/// Sent whenever something deals damage to an enemy
+pub struct DamageEvent {
+    pub target: Entity,
+    /// world position where the damage number should appear
+    pub position: Vec3,
+    pub amount: f32,
+    pub kind: DamageKind,
+    pub crit: bool,
+    /// status effect the hit applies to the target
+    pub status: Option<StatusKind>,
+}
+
+#[derive(Component)]
+pub struct DamageNumber {
+    target: Entity,
+    kind: DamageKind,
+    amount: f32,
+    crit: bool,
+    timer: Timer,
+}
+
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DamageEvent>().add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::spawn_damage_numbers)
+                .with_system(Self::float_damage_numbers)
+                .into(),
+        );
+    }
+}
+
+impl DamagePlugin {
+    fn spawn_damage_numbers(
+        mut commands: Commands,
+        mut damage_events: EventReader<DamageEvent>,
+        mut number_query: Query<(&mut DamageNumber, &mut Text, &mut Transform)>,
+        my_assets: Res<MyAssets>,
+    ) {
+        for event in damage_events.iter() {
+            if event.kind.is_continuous() {
+                // crits get a number of their own, a few of them would otherwise turn
+                // the whole stream of damage into one big crit
+                let existing = number_query.iter_mut().find(|(number, _, _)| {
+                    number.target == event.target
+                        && number.kind == event.kind
+                        && number.crit == event.crit
+                        && number.timer.elapsed_secs() < AGGREGATE_WINDOW
+                });
+                if let Some((mut number, mut text, mut transform)) = existing {
+                    number.amount += event.amount;
+                    number.timer.reset();
+                    text.sections[0].value = damage_text(number.amount, number.crit);
+                    // keep the number hovering over the target while the damage keeps coming
+                    transform.translation = number_position(event.position, number.crit);
+                    continue;
+                }
+            }
+
+            commands
+                .spawn_bundle(Text2dBundle {
+                    text: Text::with_section(
+                        damage_text(event.amount, event.crit),
+                        TextStyle {
+                            font: my_assets.font.clone(),
+                            font_size: if event.crit { 24. } else { 16. },
+                            color: damage_color(event.kind, event.crit),
+                        },
+                        TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    ),
+                    transform: Transform {
+                        translation: number_position(event.position, event.crit),
+                        // render the glyphs at a higher resolution than they're displayed at
+                        scale: Vec3::splat(0.5),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(Name::new("Damage Number"))
+                .insert(DamageNumber {
+                    target: event.target,
+                    kind: event.kind,
+                    amount: event.amount,
+                    crit: event.crit,
+                    timer: Timer::from_seconds(NUMBER_LIFETIME, false),
+                });
+        }
+    }
+
+    fn float_damage_numbers(
+        mut commands: Commands,
+        mut number_query: Query<(Entity, &mut DamageNumber, &mut Text, &mut Transform)>,
+        time: Res<Time>,
+    ) {
+        for (number_e, mut number, mut text, mut transform) in number_query.iter_mut() {
+            number.timer.tick(time.delta());
+            if number.timer.finished() {
+                commands.entity(number_e).despawn_recursive();
+                continue;
+            }
+            transform.translation.y += FLOAT_SPEED * time.delta_seconds();
+            text.sections[0]
+                .style
+                .color
+                .set_a(number.timer.percent_left());
+        }
+    }
+}
+
+/// Crits sit higher, so they don't cover the number of the same damage they were split from
+fn number_position(position: Vec3, crit: bool) -> Vec3 {
+    let height = if crit { 20. } else { 10. };
+    Vec3::new(position.x, position.y + height, 10.)
+}
+
+fn damage_color(kind: DamageKind, crit: bool) -> Color {
+    if crit {
+        Color::YELLOW
+    } else {
+        kind.color()
+    }
+}
+
+fn damage_text(amount: f32, crit: bool) -> String {
+    // continuous damage starts out as fractions of a point
+    let amount = if amount < 1. {
+        format!("{:.1}", amount)
+    } else {
+        format!("{:.0}", amount)
+    };
+    if crit {
+        format!("{}!", amount)
+    } else {
+        amount
+    }
+}
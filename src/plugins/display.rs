@@ -0,0 +1,136 @@
+use bevy::{prelude::*, render::camera::OrthographicProjection};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{GameState, MainCamera};
+
+use super::settings::Settings;
+
+/// The camera system that fits the view to the window
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct FitCamera;
+
+/// How the game view is fit into a window of a different shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ViewScaling {
+    /// black bars around the view
+    #[default]
+    Letterbox,
+    /// more of the world around the view
+    Extend,
+    /// a whole number of screen pixels per world pixel, with black bars around the view
+    PixelPerfect,
+}
+
+impl ViewScaling {
+    /// Size of the world a window of `window` pixels shows when fitting `view` into it
+    pub fn visible_size(&self, view: Vec2, window: Vec2) -> Vec2 {
+        let scale = (window.x / view.x).min(window.y / view.y);
+        match self {
+            ViewScaling::Letterbox | ViewScaling::Extend => window / scale,
+            ViewScaling::PixelPerfect => window / scale.floor().max(1.),
+        }
+    }
+}
+
+/// Covers one side of the window outside the view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+enum LetterboxBar {
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+pub struct DisplayPlugin;
+
+impl Plugin for DisplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ViewScaling>()
+            .add_system(Self::toggle_fullscreen)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .after(FitCamera)
+                    .with_system(Self::letterbox)
+                    .into(),
+            );
+    }
+}
+
+impl DisplayPlugin {
+    /// The window itself is switched over by the settings plugin
+    fn toggle_fullscreen(keyboard: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+        if keyboard.just_pressed(KeyCode::F11) {
+            settings.fullscreen = !settings.fullscreen;
+            settings.save();
+        }
+    }
+
+    fn letterbox(
+        mut commands: Commands,
+        camera_query: Query<(Entity, &OrthographicProjection), With<MainCamera>>,
+        mut bar_query: Query<(&LetterboxBar, &mut Transform, &mut Sprite, &mut Visibility)>,
+        view_scaling: Res<ViewScaling>,
+    ) {
+        let (camera_e, projection) = match camera_query.get_single() {
+            Ok(camera) => camera,
+            Err(_) => return,
+        };
+
+        if bar_query.is_empty() {
+            commands.entity(camera_e).with_children(|builder| {
+                for bar in [
+                    LetterboxBar::Left,
+                    LetterboxBar::Right,
+                    LetterboxBar::Bottom,
+                    LetterboxBar::Top,
+                ] {
+                    builder
+                        .spawn_bundle(SpriteBundle {
+                            sprite: Sprite {
+                                color: Color::BLACK,
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .insert(Name::new("Letterbox"))
+                        .insert(bar);
+                }
+            });
+            return;
+        }
+
+        // the view spans 0..view with the same margin on either side of it
+        let view = Vec2::new(
+            projection.right + projection.left,
+            projection.top + projection.bottom,
+        );
+        let height = projection.top - projection.bottom;
+        for (bar, mut transform, mut sprite, mut visibility) in bar_query.iter_mut() {
+            visibility.is_visible = *view_scaling != ViewScaling::Extend;
+
+            let (center, size) = match bar {
+                LetterboxBar::Left => (
+                    Vec2::new(projection.left / 2., view.y / 2.),
+                    Vec2::new(-projection.left, height),
+                ),
+                LetterboxBar::Right => (
+                    Vec2::new((view.x + projection.right) / 2., view.y / 2.),
+                    Vec2::new(projection.right - view.x, height),
+                ),
+                LetterboxBar::Bottom => (
+                    Vec2::new(view.x / 2., projection.bottom / 2.),
+                    Vec2::new(view.x, -projection.bottom),
+                ),
+                LetterboxBar::Top => (
+                    Vec2::new(view.x / 2., (view.y + projection.top) / 2.),
+                    Vec2::new(view.x, projection.top - view.y),
+                ),
+            };
+            // just in front of the camera, over everything else
+            transform.translation = center.extend(-1.);
+            sprite.custom_size = Some(size);
+        }
+    }
+}
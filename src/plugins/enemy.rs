@@ -3,23 +3,41 @@ use bevy_ecs_ldtk::prelude::*;
 use bevy_inspector_egui::Inspectable;
 use bevy_rapier2d::prelude::*;
 use iyes_loopless::prelude::*;
-use rand::{self, thread_rng, Rng};
+use rand::Rng;
 use std::time::Duration;
 
 use crate::{GameState, MyAssets};
 
 use super::{
-    player::{Direction, Player},
+    damage::DamageEvent,
+    particles::{ParticleEffect, ParticleEmitter},
+    player::{Direction, FlashingTimer, Player},
+    rng::GameRng,
+    score::Score,
+    settings::Settings,
+    sprite_animation::{SpriteAnimation, SpriteClip},
+    status::StatusEffects,
+    tilemap::{Wading, WATER_SPEED},
+    timestep::{tick_delta, AppGameTickExt, InterpolatedTransform, TickSubstage, TIMESTEP},
     ColliderBundle,
 };
 
+/// Enemies only go after a player this close
+const CHASE_RANGE: f32 = 100.;
+/// Kills between each upgrade to the player's projectiles
+const KILLS_PER_UPGRADE: u32 = 10;
+const ENEMY_FRAME_TIME: f32 = 0.15;
+
 #[derive(Debug, Component, Inspectable)]
 pub struct Enemy {
     pub hp: f32,
+    /// pixels per second it chases the player at, before slows and water
     pub velocity: f32,
     pub direction: Direction,
     pub is_moving: bool,
     pub slashed: bool,
+    /// score for killing it, before the combo multiplier
+    pub points: u32,
 }
 
 impl Default for Enemy {
@@ -27,26 +45,43 @@ impl Default for Enemy {
         Self {
             hp: 10.,
             direction: Direction::None,
-            velocity: 200.,
+            velocity: 40.,
             is_moving: false,
             slashed: false,
+            points: 100,
         }
     }
 }
 
 pub struct EnemySpawnTimer {
-    timer: Timer,
+    pub timer: Timer,
+    /// number of enemies spawned so far
+    pub wave: u32,
+}
+
+impl Default for EnemySpawnTimer {
+    fn default() -> Self {
+        Self {
+            // create the repeating timer
+            timer: Timer::new(Duration::from_secs(5), true),
+            wave: 0,
+        }
+    }
 }
 
 #[derive(Bundle, Default, LdtkEntity)]
 pub struct EnemyBundle {
-    #[sprite_bundle("background.png")]
+    #[sprite_sheet_bundle("background.png", 16., 16., 1, 1, 0., 0)]
     #[bundle]
-    pub sprite_bundle: SpriteBundle,
+    pub sprite_sheet_bundle: SpriteSheetBundle,
+    #[with(enemy_animation)]
+    pub animation: SpriteAnimation,
     #[from_entity_instance]
     #[bundle]
     pub collider_bundle: ColliderBundle,
     pub enemy: Enemy,
+    pub status_effects: StatusEffects,
+    pub interpolated_transform: InterpolatedTransform,
     #[worldly]
     pub worldly: Worldly,
     // The whole EntityInstance can be stored directly as an EntityInstance component
@@ -58,7 +93,8 @@ pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
+        app.add_tick_system_set(
+            TickSubstage::Gameplay,
             ConditionSet::new()
                 .run_in_state(GameState::Playing)
                 // .with_system(Self::death)
@@ -67,67 +103,74 @@ impl Plugin for EnemyPlugin {
                 .with_system(Self::hit)
                 .into(),
         )
-        .add_startup_system(Self::setup_enemy_spawning);
+        .add_tick_system_set(
+            TickSubstage::Damage,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::take_damage)
+                .into(),
+        )
+        .init_resource::<EnemySpawnTimer>();
     }
 }
 
 impl EnemyPlugin {
-    fn setup_enemy_spawning(mut commands: Commands) {
-        commands.insert_resource(EnemySpawnTimer {
-            // create the repeating timer
-            timer: Timer::new(Duration::from_secs(5), true),
-        })
-    }
-
     fn spawn_enemies(
         mut commands: Commands,
-        time: Res<Time>,
         mut spawn_timer: ResMut<EnemySpawnTimer>,
-        level_query: Query<&Handle<LdtkLevel>, (Without<OrthographicProjection>, Without<Player>)>,
+        level_query: Query<
+            (&Handle<LdtkLevel>, &Transform),
+            (Without<OrthographicProjection>, Without<Player>),
+        >,
+        level_selection: Res<LevelSelection>,
         ldtk_levels: Res<Assets<LdtkLevel>>,
         my_assets: Res<MyAssets>,
+        mut rng: ResMut<GameRng>,
     ) {
-        for level_handle in level_query.iter() {
-            if let Some(ldtk_level) = ldtk_levels.get(level_handle) {
-                // tick the timer
-                spawn_timer.timer.tick(time.delta());
-
-                if spawn_timer.timer.finished() {
-                    commands
-                        .spawn_bundle(SpriteBundle {
-                            texture: my_assets.bg.clone(),
-                            ..Default::default()
-                        })
-                        .insert(Enemy::default())
-                        .insert(Name::new("Enemy"))
-                        .insert(Transform::from_xyz(
-                            thread_rng().gen_range(0.0..ldtk_level.level.px_wid as f32 - 20.),
-                            thread_rng().gen_range(0.0..ldtk_level.level.px_hei as f32 - 20.),
-                            2.,
-                        ))
-                        .insert(GravityScale(0.))
-                        .insert(Collider::cuboid(7., 7.))
-                        .insert(RigidBody::Dynamic)
-                        .insert(Sensor)
-                        .insert(LockedAxes::ROTATION_LOCKED);
-                }
+        // enemies only spawn in the level the player is in
+        let current_level = level_query.iter().find_map(|(level_handle, level_transform)| {
+            ldtk_levels
+                .get(level_handle)
+                .filter(|ldtk_level| level_selection.is_match(&0, &ldtk_level.level))
+                .map(|ldtk_level| (ldtk_level, level_transform))
+        });
+
+        if let Some((ldtk_level, level_transform)) = current_level {
+            // tick the timer
+            spawn_timer.timer.tick(tick_delta());
+
+            if spawn_timer.timer.finished() {
+                spawn_timer.wave += 1;
+                let position = Vec3::new(
+                    level_transform.translation.x
+                        + rng.spawning.gen_range(0.0..ldtk_level.level.px_wid as f32 - 20.),
+                    level_transform.translation.y
+                        + rng.spawning.gen_range(0.0..ldtk_level.level.px_hei as f32 - 20.),
+                    2.,
+                );
+                spawn_enemy(&mut commands, &my_assets, position, Enemy::default());
             }
         }
     }
 
     fn in_range(
-        mut enemy_query: Query<&mut Transform, (With<Enemy>, Without<Player>)>,
+        mut enemy_query: Query<
+            (&Enemy, &mut Transform, &StatusEffects, Option<&Wading>),
+            Without<Player>,
+        >,
         player_query: Query<&Transform, With<Player>>,
     ) {
-        enemy_query.for_each_mut(|mut enemy_transform| {
+        enemy_query.for_each_mut(|(enemy, mut enemy_transform, status_effects, wading)| {
             let player_transform = player_query.single();
-            let distance = enemy_transform
-                .translation
-                .distance(player_transform.translation);
-            if distance < 100. {
-                enemy_transform.translation = enemy_transform
-                    .translation
-                    .lerp(player_transform.translation, 0.01);
+            let offset = (player_transform.translation - enemy_transform.translation).truncate();
+            if offset.length() < CHASE_RANGE {
+                let velocity = match wading {
+                    Some(_) => enemy.velocity * status_effects.speed_multiplier() * WATER_SPEED,
+                    None => enemy.velocity * status_effects.speed_multiplier(),
+                };
+                // stops on the player rather than overshooting
+                enemy_transform.translation +=
+                    offset.clamp_length_max(velocity * TIMESTEP).extend(0.);
             }
         })
     }
@@ -138,7 +181,7 @@ impl EnemyPlugin {
         sensor_enemy_query: Query<Entity, (With<Enemy>, With<Sensor>)>,
         enemy_query: Query<Entity, (With<Enemy>, Without<Sensor>)>,
         rapier_context: Res<RapierContext>,
-        time: Res<Time>,
+        settings: Res<Settings>,
     ) {
         for enemy_e in enemy_query.iter() {
             commands.entity(enemy_e).insert(Sensor);
@@ -146,13 +189,83 @@ impl EnemyPlugin {
         for (mut player, player_e) in player_query.iter_mut() {
             for enemy_e in sensor_enemy_query.iter() {
                 if rapier_context.intersection_pair(player_e, enemy_e) == Some(true) {
-                    player.hp -= 1. * time.delta_seconds();
+                    player.hp -= settings.difficulty.damage_taken() * TIMESTEP;
+                }
+            }
+        }
+    }
+
+    fn take_damage(
+        mut commands: Commands,
+        mut damage_events: EventReader<DamageEvent>,
+        mut enemy_query: Query<(&mut Enemy, &mut StatusEffects, &Transform)>,
+        mut player_query: Query<&mut Player>,
+        mut score: ResMut<Score>,
+    ) {
+        for event in damage_events.iter() {
+            if let Ok((mut enemy, mut status_effects, transform)) = enemy_query.get_mut(event.target) {
+                // already despawning from an earlier hit this frame
+                if death(&enemy) {
+                    continue;
+                }
+                enemy.hp -= event.amount;
+                if let Some(status) = event.status {
+                    status_effects.apply(status);
+                }
+                if death(&enemy) {
+                    commands.entity(event.target).despawn();
+                    commands.spawn_bundle(
+                        ParticleEmitter::new(ParticleEffect::death(), Vec2::Y)
+                            .bundle(transform.translation.truncate()),
+                    );
+                    score.add_kill(enemy.points);
+                    if score.kills % KILLS_PER_UPGRADE == 0 {
+                        let reward = score.kills / KILLS_PER_UPGRADE - 1;
+                        for mut player in player_query.iter_mut() {
+                            player.upgrades = player.upgrades.upgraded(reward);
+                            info!("Upgraded projectiles to {:?}", player.upgrades);
+                        }
+                    }
+                } else {
+                    commands.entity(event.target).insert(FlashingTimer {
+                        timer: Timer::new(Duration::from_millis(50), true),
+                    });
                 }
             }
         }
     }
 }
 
+/// The enemies' only clip, a single frame until they have a proper sprite sheet
+fn enemy_clip() -> SpriteClip {
+    SpriteClip::new(0..=0, ENEMY_FRAME_TIME)
+}
+
+fn enemy_animation(_: &EntityInstance) -> SpriteAnimation {
+    SpriteAnimation::new(enemy_clip())
+}
+
+/// Spawns an enemy outside of the map, e.g. for a wave or a loaded save
+pub fn spawn_enemy(commands: &mut Commands, my_assets: &MyAssets, position: Vec3, enemy: Enemy) -> Entity {
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            texture_atlas: my_assets.enemy_sheet.clone(),
+            ..Default::default()
+        })
+        .insert(SpriteAnimation::new(enemy_clip()))
+        .insert(enemy)
+        .insert(StatusEffects::default())
+        .insert(InterpolatedTransform::default())
+        .insert(Name::new("Enemy"))
+        .insert(Transform::from_translation(position))
+        .insert(GravityScale(0.))
+        .insert(Collider::cuboid(7., 7.))
+        .insert(RigidBody::Dynamic)
+        .insert(Sensor)
+        .insert(LockedAxes::ROTATION_LOCKED)
+        .id()
+}
+
 pub fn death(enemy: &Enemy) -> bool {
     if enemy.hp <= 0. {
         return true;
@@ -0,0 +1,217 @@
+use bevy::{ecs::system::EntityCommands, prelude::*};
+use bevy_ecs_ldtk::{
+    ldtk::{EntityInstance, LayerInstance, Level},
+    prelude::*,
+};
+use iyes_loopless::prelude::*;
+use serde_json::Value;
+
+use crate::{GameState, MainCamera};
+
+use super::{enemy::EnemyBundle, player::PlayerBundle, tilemap::{CameraFocus, DestructibleBundle, HazardBundle, PitBundle, WallBundle, WaterBundle}};
+
+const MAP: &str = include_str!("../../assets/thm_map.ldtk");
+
+fn insert_int_cell<B: LdtkIntCell + Bundle>(
+    tile: &mut EntityCommands,
+    cell: IntGridCell,
+    layer_instance: &LayerInstance,
+) {
+    tile.insert_bundle(B::bundle_int_cell(cell, layer_instance));
+}
+
+/// Builds the bundle the way `register_ldtk_entity` does, from the `LdtkEntity` derive
+fn insert_entity<B: LdtkEntity + Bundle>(
+    entity: &mut EntityCommands,
+    instance: &EntityInstance,
+    layer_instance: &LayerInstance,
+    translation: Vec3,
+    asset_server: &AssetServer,
+    texture_atlases: &mut Assets<TextureAtlas>,
+) {
+    entity
+        .insert_bundle(B::bundle_entity(
+            instance,
+            layer_instance,
+            None,
+            None,
+            asset_server,
+            texture_atlases,
+        ))
+        // placed after the bundle is built, as `LdtkPlugin` places it
+        .insert(Transform::from_translation(translation));
+}
+
+/// Spawns `thm_map.ldtk` the way `LdtkPlugin` would, without needing a renderer
+pub struct HeadlessLevelPlugin;
+
+impl Plugin for HeadlessLevelPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<LdtkLevel>()
+            .add_asset::<TextureAtlas>()
+            .add_enter_system(GameState::Playing, Self::spawn_world);
+    }
+}
+
+impl HeadlessLevelPlugin {
+    fn spawn_world(
+        mut commands: Commands,
+        mut ldtk_levels: ResMut<Assets<LdtkLevel>>,
+        asset_server: Res<AssetServer>,
+        mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    ) {
+        commands
+            .spawn_bundle(OrthographicCameraBundle::new_2d())
+            .insert(MainCamera)
+            .insert(CameraFocus::default());
+
+        let map: Value = serde_json::from_str(MAP).expect("thm_map.ldtk should be valid JSON");
+        let level_values = map["levels"]
+            .as_array()
+            .expect("thm_map.ldtk should have levels");
+
+        // LDtk's y axis points down, so levels are placed relative to the bottom of the world
+        let world_height = level_values
+            .iter()
+            .map(|level| level["worldY"].as_i64().unwrap_or(0) + level["pxHei"].as_i64().unwrap_or(0))
+            .max()
+            .unwrap_or(0);
+
+        for level_value in level_values {
+            let level: Level =
+                serde_json::from_value(level_value.clone()).expect("level should deserialize");
+            let level_translation = Vec3::new(
+                level.world_x as f32,
+                (world_height - level.world_y as i64 - level.px_hei as i64) as f32,
+                0.,
+            );
+            let level_height = level.px_hei as f32;
+
+            let level_entity = commands
+                .spawn()
+                .insert(Name::new(level.identifier.clone()))
+                .insert(ldtk_levels.add(LdtkLevel {
+                    level,
+                    background_image: None,
+                }))
+                .insert(Transform::from_translation(level_translation))
+                .insert(GlobalTransform::default())
+                .id();
+
+            let layers = level_value["layerInstances"].as_array().cloned().unwrap_or_default();
+            for layer in layers.iter() {
+                match layer["__type"].as_str() {
+                    Some("IntGrid") => Self::spawn_int_grid(&mut commands, level_entity, layer),
+                    Some("Entities") => {
+                        let layer_instance: LayerInstance = serde_json::from_value(layer.clone())
+                            .expect("layer should deserialize");
+                        for instance in layer["entityInstances"].as_array().into_iter().flatten() {
+                            Self::spawn_entity(
+                                &mut commands,
+                                instance,
+                                &layer_instance,
+                                level_translation,
+                                level_height,
+                                &asset_server,
+                                &mut texture_atlases,
+                            );
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    fn spawn_int_grid(commands: &mut Commands, level_entity: Entity, layer: &Value) {
+        let layer_instance: LayerInstance =
+            serde_json::from_value(layer.clone()).expect("layer should deserialize");
+        let width = layer_instance.c_wid;
+        let height = layer_instance.c_hei;
+        let grid_size = layer_instance.grid_size as f32;
+
+        // LdtkPlugin puts the tiles in a chunk below the level, which `spawn_wall_collision` expects
+        let chunk = commands
+            .spawn()
+            .insert(Transform::default())
+            .insert(GlobalTransform::default())
+            .id();
+        commands.entity(level_entity).add_child(chunk);
+
+        commands.entity(chunk).with_children(|builder| {
+            for (i, &value) in layer_instance.int_grid_csv.iter().enumerate() {
+                if value == 0 {
+                    continue;
+                }
+                let grid_coords = GridCoords {
+                    x: i as i32 % width,
+                    y: height - 1 - i as i32 / width,
+                };
+                let cell = IntGridCell { value };
+                let mut tile = builder.spawn();
+                tile.insert(grid_coords)
+                    .insert(cell)
+                    .insert(Transform::from_xyz(
+                        (grid_coords.x as f32 + 0.5) * grid_size,
+                        (grid_coords.y as f32 + 0.5) * grid_size,
+                        0.,
+                    ))
+                    .insert(GlobalTransform::default());
+                // the same registrations `app()` makes with `register_ldtk_int_cell`
+                match value {
+                    1 => insert_int_cell::<WallBundle>(&mut tile, cell, &layer_instance),
+                    2 => insert_int_cell::<HazardBundle>(&mut tile, cell, &layer_instance),
+                    3 => insert_int_cell::<WaterBundle>(&mut tile, cell, &layer_instance),
+                    4 => insert_int_cell::<PitBundle>(&mut tile, cell, &layer_instance),
+                    5 => insert_int_cell::<DestructibleBundle>(&mut tile, cell, &layer_instance),
+                    _ => (),
+                }
+            }
+        });
+    }
+
+    fn spawn_entity(
+        commands: &mut Commands,
+        instance_value: &Value,
+        layer_instance: &LayerInstance,
+        level_translation: Vec3,
+        level_height: f32,
+        asset_server: &AssetServer,
+        texture_atlases: &mut Assets<TextureAtlas>,
+    ) {
+        let instance: EntityInstance =
+            serde_json::from_value(instance_value.clone()).expect("entity should deserialize");
+
+        let coord = |value: &Value, i| value[i].as_f64().unwrap_or(0.) as f32;
+        let px = Vec2::new(coord(&instance_value["px"], 0), coord(&instance_value["px"], 1));
+        let pivot = Vec2::new(
+            coord(&instance_value["__pivot"], 0),
+            coord(&instance_value["__pivot"], 1),
+        );
+        let size = Vec2::new(instance.width as f32, instance.height as f32);
+        let center = px + (Vec2::splat(0.5) - pivot) * size;
+        let translation =
+            level_translation + Vec3::new(center.x, level_height - center.y, 2.);
+
+        // the same registrations `app()` makes with `register_ldtk_entity`
+        match instance.identifier.as_str() {
+            "Player" => insert_entity::<PlayerBundle>(
+                &mut commands.spawn(),
+                &instance,
+                layer_instance,
+                translation,
+                asset_server,
+                texture_atlases,
+            ),
+            "Enemy" => insert_entity::<EnemyBundle>(
+                &mut commands.spawn(),
+                &instance,
+                layer_instance,
+                translation,
+                asset_server,
+                texture_atlases,
+            ),
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{GameState, MyAssets, HEIGHT};
+
+use super::{
+    enemy::EnemySpawnTimer,
+    player::{Player, Weapon, MAX_HP},
+    score::Score,
+    timestep::{GameTick, TIMESTEP},
+};
+
+/// Font size of HUD text in a window of the default height
+const FONT_SIZE: f32 = 20.;
+
+/// Text that grows and shrinks with the window
+#[derive(Component)]
+struct HudText;
+
+#[derive(Component)]
+struct HealthBar;
+
+#[derive(Component)]
+struct WeaponIcon;
+
+#[derive(Component)]
+struct WeaponText;
+
+/// Background of the heat bar, only shown for the laser
+#[derive(Component)]
+struct HeatGauge;
+
+#[derive(Component)]
+struct HeatBar;
+
+/// Wave, time, score and combo in the corner
+#[derive(Component)]
+struct StatsText;
+
+pub struct HudPlugin;
+
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Playing, Self::spawn_hud)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::update_health)
+                    .with_system(Self::update_weapon)
+                    .with_system(Self::update_stats)
+                    .with_system(Self::scale_text)
+                    .into(),
+            );
+    }
+}
+
+impl HudPlugin {
+    fn spawn_hud(mut commands: Commands, my_assets: Res<MyAssets>) {
+        let text_style = TextStyle {
+            font: my_assets.font.clone(),
+            font_size: FONT_SIZE,
+            color: Color::WHITE,
+        };
+        // sizes are percentages of the window so the HUD scales with it,
+        // and bevy's UI has y up, so `bottom` is measured from the top of the window
+        let absolute = |position: Rect<Val>, size: Size<Val>| Style {
+            position_type: PositionType::Absolute,
+            position,
+            size,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        };
+        let fill = || Style {
+            size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+            ..Default::default()
+        };
+
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    ..Default::default()
+                },
+                color: Color::NONE.into(),
+                ..Default::default()
+            })
+            .insert(Name::new("HUD"))
+            .with_children(|hud| {
+                hud.spawn_bundle(NodeBundle {
+                    style: absolute(
+                        Rect {
+                            left: Val::Percent(2.),
+                            bottom: Val::Percent(3.),
+                            ..Default::default()
+                        },
+                        Size::new(Val::Percent(25.), Val::Percent(3.)),
+                    ),
+                    color: Color::rgb(0.2, 0.2, 0.2).into(),
+                    ..Default::default()
+                })
+                .with_children(|frame| {
+                    frame
+                        .spawn_bundle(NodeBundle {
+                            style: fill(),
+                            color: Color::GREEN.into(),
+                            ..Default::default()
+                        })
+                        .insert(HealthBar);
+                });
+
+                hud.spawn_bundle(NodeBundle {
+                    style: absolute(
+                        Rect {
+                            left: Val::Percent(2.),
+                            top: Val::Percent(3.),
+                            ..Default::default()
+                        },
+                        Size::new(Val::Percent(30.), Val::Percent(6.)),
+                    ),
+                    color: Color::NONE.into(),
+                    ..Default::default()
+                })
+                .with_children(|panel| {
+                    panel
+                        .spawn_bundle(ImageBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(12.), Val::Percent(100.)),
+                                ..Default::default()
+                            },
+                            image: my_assets.arrow.clone().into(),
+                            ..Default::default()
+                        })
+                        .insert(WeaponIcon);
+                    panel
+                        .spawn_bundle(TextBundle {
+                            style: Style {
+                                margin: Rect::all(Val::Percent(3.)),
+                                ..Default::default()
+                            },
+                            text: Text::with_section("", text_style.clone(), Default::default()),
+                            ..Default::default()
+                        })
+                        .insert(WeaponText)
+                        .insert(HudText);
+                    panel
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(40.), Val::Percent(40.)),
+                                ..Default::default()
+                            },
+                            color: Color::rgb(0.2, 0.2, 0.2).into(),
+                            ..Default::default()
+                        })
+                        .insert(HeatGauge)
+                        .with_children(|gauge| {
+                            gauge
+                                .spawn_bundle(NodeBundle {
+                                    style: fill(),
+                                    color: Color::ORANGE.into(),
+                                    ..Default::default()
+                                })
+                                .insert(HeatBar);
+                        });
+                });
+
+                hud.spawn_bundle(TextBundle {
+                    style: absolute(
+                        Rect {
+                            right: Val::Percent(2.),
+                            bottom: Val::Percent(3.),
+                            ..Default::default()
+                        },
+                        Size::default(),
+                    ),
+                    text: Text {
+                        sections: vec![
+                            TextSection {
+                                value: String::new(),
+                                style: text_style.clone(),
+                            };
+                            3
+                        ],
+                        alignment: TextAlignment {
+                            horizontal: HorizontalAlign::Right,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .insert(StatsText)
+                .insert(HudText);
+            });
+    }
+
+    fn update_health(
+        player_query: Query<&Player, Changed<Player>>,
+        mut bar_query: Query<(&mut Style, &mut UiColor), With<HealthBar>>,
+    ) {
+        let player = match player_query.get_single() {
+            Ok(player) => player,
+            Err(_) => return,
+        };
+        let health = (player.hp / MAX_HP).clamp(0., 1.);
+        for (mut style, mut color) in bar_query.iter_mut() {
+            style.size.width = Val::Percent(health * 100.);
+            // red when nearly dead, green at full health
+            color.0 = Color::rgb(1. - health, health, 0.);
+        }
+    }
+
+    fn update_weapon(
+        player_query: Query<&Player, Changed<Player>>,
+        mut icon_query: Query<&mut UiColor, (With<WeaponIcon>, Without<HeatBar>)>,
+        mut text_query: Query<&mut Text, With<WeaponText>>,
+        mut gauge_query: Query<&mut Style, (With<HeatGauge>, Without<HeatBar>)>,
+        mut bar_query: Query<(&mut Style, &mut UiColor), (With<HeatBar>, Without<HeatGauge>)>,
+    ) {
+        let player = match player_query.get_single() {
+            Ok(player) => player,
+            Err(_) => return,
+        };
+        let (name, icon_color, has_heat) = match player.weapon {
+            Weapon::Gun => ("Gun  ∞", Color::WHITE, false),
+            Weapon::Laser => ("Laser", Color::rgb(1., 0.45, 0.2), true),
+        };
+
+        for mut color in icon_query.iter_mut() {
+            color.0 = icon_color;
+        }
+        for mut text in text_query.iter_mut() {
+            if text.sections[0].value != name {
+                text.sections[0].value = name.to_string();
+            }
+        }
+        for mut style in gauge_query.iter_mut() {
+            style.display = if has_heat { Display::Flex } else { Display::None };
+        }
+        for (mut style, mut color) in bar_query.iter_mut() {
+            style.size.width = Val::Percent(player.heat * 100.);
+            color.0 = if player.overheated {
+                Color::RED
+            } else {
+                Color::ORANGE
+            };
+        }
+    }
+
+    fn update_stats(
+        mut text_query: Query<&mut Text, With<StatsText>>,
+        spawn_timer: Res<EnemySpawnTimer>,
+        score: Res<Score>,
+        tick: Res<GameTick>,
+    ) {
+        let seconds = (tick.tick as f32 * TIMESTEP) as u32;
+        let next_wave = spawn_timer.timer.duration() - spawn_timer.timer.elapsed();
+        let lines = [
+            format!("Wave {}  next in {:.0}s\n", spawn_timer.wave, next_wave.as_secs_f32().ceil()),
+            format!("{:02}:{:02}\n", seconds / 60, seconds % 60),
+            format!("Score {}  x{:.2}  Kills {}", score.points, score.multiplier, score.kills),
+        ];
+
+        for mut text in text_query.iter_mut() {
+            // only touched when it changes, so the text isn't laid out again every frame
+            if text.sections.iter().zip(&lines).any(|(section, line)| section.value != *line) {
+                for (section, line) in text.sections.iter_mut().zip(&lines) {
+                    section.value = line.clone();
+                }
+            }
+        }
+    }
+
+    fn scale_text(windows: Res<Windows>, mut text_query: Query<&mut Text, With<HudText>>) {
+        let window = match windows.get_primary() {
+            Some(window) => window,
+            None => return,
+        };
+        let font_size = FONT_SIZE * window.height() / HEIGHT;
+
+        for mut text in text_query.iter_mut() {
+            if text.sections.iter().any(|section| section.style.font_size != font_size) {
+                for section in text.sections.iter_mut() {
+                    section.style.font_size = font_size;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use std::path::PathBuf;
+
+use bevy::{app::AppExit, input::InputSystem, prelude::*};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{GameState, MainCamera};
+
+use super::{
+    player::Player,
+    rng::GameRng,
+    timestep::{AppGameTickExt, GameTick, TickSubstage, TIMESTEP},
+    utils::to_world_coordinates,
+};
+
+/// Everything the player did during a single gameplay tick
+///
+/// Gameplay systems read this instead of `Input<KeyCode>` or `Windows`,
+/// so it can come from the keyboard or from a replay.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub dash: bool,
+    /// fire button is held
+    pub fire: bool,
+    pub fire_pressed: bool,
+    pub fire_released: bool,
+    pub slash: bool,
+    pub select_gun: bool,
+    pub select_laser: bool,
+    /// cursor position in world coordinates
+    pub cursor: Option<Vec2>,
+}
+
+impl PlayerInput {
+    fn clear_presses(&mut self) {
+        self.dash = false;
+        self.fire_pressed = false;
+        self.fire_released = false;
+        self.slash = false;
+        self.select_gun = false;
+        self.select_laser = false;
+    }
+}
+
+/// Input sampled every frame, waiting to be consumed by the next tick
+///
+/// Presses are kept until a tick sees them so none are dropped or repeated
+/// when a frame runs zero or several ticks.
+#[derive(Default)]
+pub struct PendingInput(PlayerInput);
+
+impl PendingInput {
+    /// Drops everything waiting for the next tick, e.g. when the game pauses or resumes
+    pub fn clear(&mut self) {
+        self.0 = Default::default();
+    }
+}
+
+/// Keys that move the player, and that menus move their selection with
+const MOVEMENT_KEYS: [KeyCode; 8] = [
+    KeyCode::W,
+    KeyCode::S,
+    KeyCode::A,
+    KeyCode::D,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Left,
+    KeyCode::Right,
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub seed: u64,
+    pub timestep: f32,
+    pub frames: Vec<PlayerInput>,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&contents).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Default)]
+pub enum ReplayMode {
+    #[default]
+    Live,
+    Recording { path: PathBuf, frames: Vec<PlayerInput> },
+    Playback { replay: Replay, frame: usize },
+}
+
+impl ReplayMode {
+    /// `--record <path>` records the run, `--replay <path>` plays one back
+    ///
+    /// A replay that can't be played back is an error rather than a live run,
+    /// so it can't be mistaken for one that played.
+    pub fn from_args() -> Result<Self, String> {
+        let mut args = std::env::args();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => {
+                    let path = args.next().ok_or("--record needs a path after it")?;
+                    return Ok(ReplayMode::Recording {
+                        path: path.into(),
+                        frames: Vec::new(),
+                    });
+                }
+                "--replay" => {
+                    let path = args.next().ok_or("--replay needs a path after it")?;
+                    let replay = Replay::load(&path)
+                        .map_err(|e| format!("couldn't load replay {}: {}", path, e))?;
+                    if replay.timestep != TIMESTEP {
+                        return Err(format!(
+                            "replay {} was recorded with a timestep of {}s, not {}s",
+                            path, replay.timestep, TIMESTEP
+                        ));
+                    }
+                    return Ok(ReplayMode::Playback { replay, frame: 0 });
+                }
+                _ => (),
+            }
+        }
+        Ok(ReplayMode::Live)
+    }
+
+    /// Seed the run has to use to play back correctly
+    pub fn seed(&self) -> Option<u64> {
+        match self {
+            ReplayMode::Playback { replay, .. } => Some(replay.seed),
+            _ => None,
+        }
+    }
+}
+
+pub struct PlayerInputPlugin;
+
+impl Plugin for PlayerInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerInput>()
+            .init_resource::<PendingInput>()
+            .init_resource::<ReplayMode>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                Self::sample_input.after(InputSystem),
+            )
+            .add_tick_system_set(
+                TickSubstage::Input,
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::next_tick_input)
+                    .into(),
+            )
+            .add_enter_system(GameState::GameOver, Self::save_replay)
+            .add_system_to_stage(CoreStage::Last, Self::save_replay_on_exit);
+    }
+}
+
+impl PlayerInputPlugin {
+    fn sample_input(
+        mut pending: ResMut<PendingInput>,
+        keyboard: Res<Input<KeyCode>>,
+        mouse: Res<Input<MouseButton>>,
+        windows: Res<Windows>,
+        q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+        tick: Res<GameTick>,
+        // movement keys still held from a menu, ignored until they're let go
+        mut held_from_pause: Local<Vec<KeyCode>>,
+    ) {
+        // presses while paused shouldn't fire off once the game resumes
+        if tick.paused {
+            pending.clear();
+            *held_from_pause = MOVEMENT_KEYS
+                .into_iter()
+                .filter(|&key| keyboard.pressed(key))
+                .collect();
+            return;
+        }
+        held_from_pause.retain(|&key| keyboard.pressed(key));
+        let pressed = |key| keyboard.pressed(key) && !held_from_pause.contains(&key);
+
+        let input = &mut pending.0;
+        input.up = pressed(KeyCode::W) || pressed(KeyCode::Up);
+        input.down = pressed(KeyCode::S) || pressed(KeyCode::Down);
+        input.left = pressed(KeyCode::A) || pressed(KeyCode::Left);
+        input.right = pressed(KeyCode::D) || pressed(KeyCode::Right);
+        input.fire = mouse.pressed(MouseButton::Left);
+
+        input.dash |= keyboard.just_pressed(KeyCode::LShift);
+        input.fire_pressed |= mouse.just_pressed(MouseButton::Left);
+        input.fire_released |= mouse.just_released(MouseButton::Left);
+        input.slash |= keyboard.just_pressed(KeyCode::F);
+        input.select_gun |= keyboard.just_pressed(KeyCode::Key1);
+        input.select_laser |= keyboard.just_pressed(KeyCode::Key2);
+
+        if let (Some(window), Ok((camera, camera_transform))) =
+            (windows.get_primary(), q_camera.get_single())
+        {
+            if let Some(mouse_position) = window.cursor_position() {
+                input.cursor = Some(
+                    to_world_coordinates(camera, camera_transform, window, mouse_position)
+                        .truncate(),
+                );
+            }
+        }
+    }
+
+    fn next_tick_input(
+        mut commands: Commands,
+        mut input: ResMut<PlayerInput>,
+        mut pending: ResMut<PendingInput>,
+        mut replay_mode: ResMut<ReplayMode>,
+        player_query: Query<(), With<Player>>,
+    ) {
+        // ticks before the level has spawned aren't part of the run
+        if player_query.is_empty() {
+            *input = PlayerInput::default();
+            return;
+        }
+
+        match &mut *replay_mode {
+            ReplayMode::Playback { replay, frame } => {
+                if let Some(recorded) = replay.frames.get(*frame) {
+                    *input = recorded.clone();
+                    *frame += 1;
+                } else {
+                    info!("Replay finished after {} ticks", frame);
+                    *input = PlayerInput::default();
+                    commands.insert_resource(ReplayMode::Live);
+                }
+            }
+            live_or_recording => {
+                *input = pending.0.clone();
+                pending.0.clear_presses();
+                if let ReplayMode::Recording { frames, .. } = live_or_recording {
+                    frames.push(input.clone());
+                }
+            }
+        }
+    }
+
+    fn save_replay(replay_mode: Res<ReplayMode>, rng: Res<GameRng>) {
+        if let ReplayMode::Recording { path, frames } = &*replay_mode {
+            let replay = Replay {
+                seed: rng.seed,
+                timestep: TIMESTEP,
+                frames: frames.clone(),
+            };
+            match ron::to_string(&replay) {
+                Ok(contents) => match std::fs::write(path, contents) {
+                    Ok(()) => info!("Saved replay to {}", path.display()),
+                    Err(e) => error!("couldn't save replay to {}: {}", path.display(), e),
+                },
+                Err(e) => error!("couldn't serialize replay: {}", e),
+            }
+        }
+    }
+
+    fn save_replay_on_exit(
+        mut exit_events: EventReader<AppExit>,
+        replay_mode: Res<ReplayMode>,
+        rng: Res<GameRng>,
+    ) {
+        if exit_events.iter().next().is_some() {
+            Self::save_replay(replay_mode, rng);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use bevy::{prelude::*, sprite::Anchor, utils::HashMap};
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{GameState, MyAssets};
+
+use super::{
+    damage::{DamageEvent, DamageKind},
+    enemy::Enemy,
+    input::PlayerInput,
+    particles::{ParticleEffect, ParticleEmitter},
+    player::{Player, Weapon},
+    rng::GameRng,
+    status::StatusKind,
+    tilemap::{nearest_destructible, Destructible, WallCollision},
+    timestep::{AppGameTickExt, TickSubstage, TIMESTEP},
+    utils::look_at,
+};
+
+/// Seconds the beam has to stay on an enemy for each stack of burning it applies
+const BURN_INTERVAL: f32 = 0.5;
+/// How far from where the beam meets a wall a destructible tile can be and still take the hit
+const WALL_REACH: f32 = 1.;
+
+/// How far the laser reaches, how it bounces off walls and how it chains between enemies
+pub struct LaserConfig {
+    /// length of the beam, all its bounces included
+    pub range: f32,
+    /// walls the beam reflects off before the one that stops it
+    pub bounces: u32,
+    /// enemies the laser jumps on to from the one it hits
+    pub chains: u32,
+    /// furthest an enemy can be from the last one for the laser to jump to it
+    pub chain_range: f32,
+    /// share of the damage left after each jump
+    pub chain_falloff: f32,
+}
+
+impl Default for LaserConfig {
+    fn default() -> Self {
+        Self {
+            range: 150.,
+            bounces: 2,
+            chains: 2,
+            chain_range: 60.,
+            chain_falloff: 0.5,
+        }
+    }
+}
+
+/// One straight piece of the drawn beam
+#[derive(Component)]
+pub struct BeamSegment;
+
+/// Sparks where a piece of the beam ends on something
+#[derive(Component)]
+pub struct LaserSpark;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    /// from the player or off a wall
+    Beam,
+    /// jumping from one enemy to the next
+    Chain,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub kind: SegmentKind,
+    /// whether it ends on something rather than running out of range
+    pub hit: bool,
+}
+
+/// Everything the laser touches in a tick
+#[derive(Debug, Default)]
+pub struct Beam {
+    pub segments: Vec<Segment>,
+    /// enemies hit, where, and the share of the laser's damage they take
+    pub hits: Vec<(Entity, Vec2, f32)>,
+    /// where it met walls, whether it bounced off them or stopped
+    pub walls: Vec<Vec2>,
+}
+
+/// Follows the beam from `origin` off any walls, then chains it on from the first enemy it hits
+pub fn trace_beam(
+    rapier_context: &RapierContext,
+    shooter: Entity,
+    origin: Vec2,
+    direction: Vec2,
+    config: &LaserConfig,
+    is_wall: impl Fn(Entity) -> bool,
+    enemies: &[(Entity, Vec2)],
+) -> Beam {
+    let is_enemy = |entity: Entity| enemies.iter().any(|(enemy_e, _)| *enemy_e == entity);
+    // enemies are sensors, so rather than `exclude_sensors` only walls and enemies stop the beam,
+    // and it passes through projectiles, pooled ones, slashes and hazard tiles
+    let stops_beam = |entity: Entity| is_wall(entity) || is_enemy(entity);
+    let filter = QueryFilter::new()
+        .exclude_collider(shooter)
+        .predicate(&stops_beam);
+    let mut beam = Beam::default();
+    let mut origin = origin;
+    let mut direction = direction.normalize_or_zero();
+    let mut remaining = config.range;
+    let mut bounces = config.bounces;
+
+    while remaining > 0. {
+        let (entity, intersection) = match rapier_context
+            .cast_ray_and_get_normal(origin, direction, remaining, true, filter)
+        {
+            Some(hit) => hit,
+            None => {
+                beam.segments.push(Segment {
+                    start: origin,
+                    end: origin + direction * remaining,
+                    kind: SegmentKind::Beam,
+                    hit: false,
+                });
+                break;
+            }
+        };
+        let end = origin + direction * intersection.toi;
+        beam.segments.push(Segment {
+            start: origin,
+            end,
+            kind: SegmentKind::Beam,
+            hit: true,
+        });
+
+        if is_enemy(entity) {
+            beam.hits.push((entity, end, 1.));
+            chain(&mut beam, config, enemies);
+            break;
+        }
+        beam.walls.push(end);
+        if bounces == 0 {
+            break;
+        }
+        bounces -= 1;
+        remaining -= intersection.toi;
+        let normal = intersection.normal;
+        direction -= 2. * direction.dot(normal) * normal;
+        // starting right on the wall would hit it again straight away
+        origin = end + normal * 0.5;
+    }
+    beam
+}
+
+/// Jumps from the last enemy hit to the nearest one it hasn't hit yet, `config.chains` times
+fn chain(beam: &mut Beam, config: &LaserConfig, enemies: &[(Entity, Vec2)]) {
+    let (_, mut from, mut share) = match beam.hits.last() {
+        Some(hit) => *hit,
+        None => return,
+    };
+    for _ in 0..config.chains {
+        let next = enemies
+            .iter()
+            .filter(|(enemy_e, _)| !beam.hits.iter().any(|(hit_e, _, _)| hit_e == enemy_e))
+            .map(|(enemy_e, position)| (*enemy_e, *position, position.distance(from)))
+            .filter(|(_, _, distance)| *distance <= config.chain_range)
+            .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+        let (enemy_e, position, _) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        share *= config.chain_falloff;
+        beam.segments.push(Segment {
+            start: from,
+            end: position,
+            kind: SegmentKind::Chain,
+            hit: true,
+        });
+        beam.hits.push((enemy_e, position, share));
+        from = position;
+    }
+}
+
+pub struct LaserPlugin;
+
+impl Plugin for LaserPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LaserConfig>().add_tick_system_set(
+            TickSubstage::Gameplay,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::fire_laser)
+                .into(),
+        );
+    }
+}
+
+impl LaserPlugin {
+    /// Heats the laser up while it's firing, and hurts and draws whatever the beam touches,
+    /// destructible walls included
+    #[allow(clippy::too_many_arguments)]
+    fn fire_laser(
+        mut commands: Commands,
+        mut player_query: Query<(&mut Player, Entity, &Transform), Without<Enemy>>,
+        enemy_query: Query<(Entity, &Transform), With<Enemy>>,
+        wall_collision_query: Query<(), With<WallCollision>>,
+        destructible_query: Query<(Entity, &GlobalTransform), With<Destructible>>,
+        mut segment_query: Query<
+            (Entity, &mut Transform, &mut Sprite),
+            (With<BeamSegment>, Without<Player>, Without<Enemy>),
+        >,
+        mut spark_query: Query<
+            (Entity, &mut Transform, &mut ParticleEmitter),
+            (With<LaserSpark>, Without<BeamSegment>, Without<Player>, Without<Enemy>),
+        >,
+        rapier_context: Res<RapierContext>,
+        input: Res<PlayerInput>,
+        config: Res<LaserConfig>,
+        my_assets: Res<MyAssets>,
+        mut damage_events: EventWriter<DamageEvent>,
+        mut rng: ResMut<GameRng>,
+        // ticks until each enemy can be set burning again
+        mut burn_cooldowns: Local<HashMap<Entity, u32>>,
+    ) {
+        burn_cooldowns.retain(|_, ticks| {
+            *ticks -= 1;
+            *ticks > 0
+        });
+        let mut beam = Beam::default();
+        if let Ok((mut player, player_e, player_transform)) = player_query.get_single_mut() {
+            let firing = matches!(player.weapon, Weapon::Laser) && input.fire && !player.overheated;
+            player.update_heat(firing);
+
+            // it can overheat this very tick
+            let aim = input.cursor.filter(|_| firing && !player.overheated);
+            if let Some(world_pos) = aim {
+                let origin = player_transform.translation.truncate();
+                let enemies: Vec<_> = enemy_query
+                    .iter()
+                    .map(|(enemy_e, transform)| (enemy_e, transform.translation.truncate()))
+                    .collect();
+                beam = trace_beam(
+                    &rapier_context,
+                    player_e,
+                    origin,
+                    world_pos - origin,
+                    &config,
+                    |entity| wall_collision_query.contains(entity),
+                    &enemies,
+                );
+
+                for (enemy_e, position, share) in beam.hits.iter() {
+                    let (amount, crit) = player.roll_damage(
+                        player.weapon.damage() * TIMESTEP * share,
+                        player.weapon.crit_chance(),
+                        &mut rng.crits,
+                    );
+                    // so how fast it stacks doesn't depend on the tick rate
+                    let status = (!burn_cooldowns.contains_key(enemy_e)).then(|| {
+                        burn_cooldowns.insert(*enemy_e, (BURN_INTERVAL / TIMESTEP).round() as u32);
+                        StatusKind::Burning
+                    });
+                    damage_events.send(DamageEvent {
+                        target: *enemy_e,
+                        position: position.extend(0.),
+                        amount,
+                        kind: DamageKind::Laser,
+                        crit,
+                        status,
+                    });
+                }
+                // walls are merged into big colliders, so the tile that was hit is found by position
+                for wall in beam.walls.iter() {
+                    let destructible =
+                        nearest_destructible(*wall, WALL_REACH, destructible_query.iter());
+                    if let Some(Ok((tile, tile_transform))) =
+                        destructible.map(|tile| destructible_query.get(tile))
+                    {
+                        let (amount, crit) = player.roll_damage(
+                            player.weapon.damage() * TIMESTEP,
+                            player.weapon.crit_chance(),
+                            &mut rng.crits,
+                        );
+                        damage_events.send(DamageEvent {
+                            target: tile,
+                            position: tile_transform.translation,
+                            amount,
+                            kind: DamageKind::Laser,
+                            crit,
+                            status: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        // segments and sparks are reused from tick to tick, and the ones left over despawned
+        let mut segment_iter = segment_query.iter_mut();
+        let mut spark_iter = spark_query.iter_mut();
+        for segment in beam.segments.iter() {
+            let offset = segment.end - segment.start;
+            let transform = Transform {
+                translation: segment.start.extend(1.),
+                rotation: look_at(offset),
+                ..Default::default()
+            };
+            let sprite = Sprite {
+                anchor: Anchor::BottomCenter,
+                custom_size: Some(Vec2::new(1., offset.length())),
+                color: match segment.kind {
+                    SegmentKind::Beam => Color::WHITE,
+                    SegmentKind::Chain => Color::rgb(0.6, 0.85, 1.),
+                },
+                ..Default::default()
+            };
+            match segment_iter.next() {
+                Some((_, mut segment_transform, mut segment_sprite)) => {
+                    *segment_transform = transform;
+                    *segment_sprite = sprite;
+                }
+                None => {
+                    commands
+                        .spawn_bundle(SpriteBundle {
+                            sprite,
+                            transform,
+                            texture: my_assets.arrow.clone(),
+                            ..Default::default()
+                        })
+                        .insert(Name::new("Beam Segment"))
+                        .insert(BeamSegment);
+                }
+            }
+            if !segment.hit {
+                continue;
+            }
+            match spark_iter.next() {
+                Some((_, mut spark_transform, mut emitter)) => {
+                    spark_transform.translation = segment.end.extend(spark_transform.translation.z);
+                    emitter.direction = -offset.normalize_or_zero();
+                }
+                None => {
+                    commands
+                        .spawn_bundle(
+                            ParticleEmitter::new(ParticleEffect::laser_hit(), -offset)
+                                .bundle(segment.end),
+                        )
+                        .insert(Name::new("Laser Spark"))
+                        .insert(LaserSpark);
+                }
+            }
+        }
+        for (segment_e, _, _) in segment_iter {
+            commands.entity(segment_e).despawn();
+        }
+        for (spark_e, _, _) in spark_iter {
+            commands.entity(spark_e).despawn();
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use bevy_ecs_ldtk::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::{GameState, MyAssets};
+
+use super::{
+    enemy::EnemySpawnTimer,
+    input::ReplayMode,
+    projectile::ProjectilePool,
+    rng::GameRng,
+    save::SaveGame,
+    score::{HighScore, HighScores, Score},
+    timestep::GameTick,
+};
+
+/// Survives a finished run being cleared away for the next one
+#[derive(Component)]
+pub struct KeepOnRestart;
+
+/// The main menu or game over screen
+#[derive(Component)]
+struct MenuScreen;
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::MainMenu, Self::spawn_main_menu)
+            .add_exit_system(GameState::MainMenu, Self::despawn_screen)
+            .add_system(Self::start_game.run_in_state(GameState::MainMenu))
+            .add_enter_system(GameState::GameOver, Self::spawn_game_over)
+            .add_exit_system(GameState::GameOver, Self::reset_run)
+            .add_system(Self::back_to_main_menu.run_in_state(GameState::GameOver));
+    }
+}
+
+impl MenuPlugin {
+    fn spawn_main_menu(
+        mut commands: Commands,
+        my_assets: Res<MyAssets>,
+        high_scores: Res<HighScores>,
+    ) {
+        let mut lines = vec![
+            ("T5HM\n\n".to_string(), 48., Color::WHITE),
+            ("Enter to play\n".to_string(), 24., Color::YELLOW),
+        ];
+        if SaveGame::exists() {
+            lines.push(("C to continue\n".to_string(), 24., Color::YELLOW));
+        }
+        lines.push(("\n".to_string(), 24., Color::WHITE));
+        lines.extend(high_score_lines(&high_scores, None));
+        spawn_screen(&mut commands, &my_assets, lines);
+    }
+
+    fn start_game(
+        mut commands: Commands,
+        keyboard: Res<Input<KeyCode>>,
+        mut tick: ResMut<GameTick>,
+        mut replay_mode: ResMut<ReplayMode>,
+    ) {
+        if keyboard.just_pressed(KeyCode::Return) {
+            commands.insert_resource(NextState(GameState::Playing));
+        }
+        if keyboard.just_pressed(KeyCode::C) {
+            match SaveGame::load() {
+                Ok(save) => {
+                    save.continue_run(&mut commands, &mut tick, &mut replay_mode);
+                    commands.insert_resource(NextState(GameState::Playing));
+                }
+                Err(e) => error!("couldn't load the save: {}", e),
+            }
+        }
+    }
+
+    /// Enters the run that just ended into the high-score table and shows it
+    fn spawn_game_over(
+        mut commands: Commands,
+        my_assets: Res<MyAssets>,
+        mut high_scores: ResMut<HighScores>,
+        score: Res<Score>,
+        spawn_timer: Res<EnemySpawnTimer>,
+        rng: Res<GameRng>,
+    ) {
+        let rank = high_scores.insert(HighScore::new(score.points, spawn_timer.wave, rng.seed));
+        if rank.is_some() {
+            high_scores.save();
+        }
+
+        let mut lines = vec![
+            ("Game over\n\n".to_string(), 48., Color::WHITE),
+            (
+                format!(
+                    "Score {}  Kills {}  Wave {}\n",
+                    score.points, score.kills, spawn_timer.wave
+                ),
+                24.,
+                Color::WHITE,
+            ),
+            (
+                if rank.is_some() {
+                    "New high score!\n\n".to_string()
+                } else {
+                    "\n".to_string()
+                },
+                24.,
+                Color::YELLOW,
+            ),
+        ];
+        lines.extend(high_score_lines(&high_scores, rank));
+        lines.push(("\nEnter for the main menu".to_string(), 24., Color::YELLOW));
+        spawn_screen(&mut commands, &my_assets, lines);
+    }
+
+    fn back_to_main_menu(mut commands: Commands, keyboard: Res<Input<KeyCode>>) {
+        if keyboard.just_pressed(KeyCode::Return) {
+            commands.insert_resource(NextState(GameState::MainMenu));
+        }
+    }
+
+    fn despawn_screen(mut commands: Commands, screen_query: Query<Entity, With<MenuScreen>>) {
+        for screen_e in screen_query.iter() {
+            commands.entity(screen_e).despawn_recursive();
+        }
+    }
+
+    /// Clears away the finished run so entering `Playing` starts a fresh one
+    fn reset_run(
+        mut commands: Commands,
+        entity_query: Query<Entity, (Without<Parent>, Without<KeepOnRestart>)>,
+        mut rng: ResMut<GameRng>,
+        mut replay_mode: ResMut<ReplayMode>,
+        mut tick: ResMut<GameTick>,
+    ) {
+        for entity in entity_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        commands.insert_resource(Score::default());
+        commands.insert_resource(EnemySpawnTimer::default());
+        // its projectiles were just despawned along with everything else
+        commands.insert_resource(ProjectilePool::default());
+        commands.insert_resource(LevelSelection::Uid(0));
+        tick.tick = 0;
+
+        *rng = GameRng::new(rand::random());
+        // a recording or playback only covers the first run
+        *replay_mode = ReplayMode::Live;
+    }
+}
+
+/// The high-score table, one line per entry, highlighting `rank`
+fn high_score_lines(high_scores: &HighScores, rank: Option<usize>) -> Vec<(String, f32, Color)> {
+    if high_scores.entries.is_empty() {
+        return vec![("No high scores yet\n".to_string(), 20., Color::GRAY)];
+    }
+    high_scores
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            (
+                format!(
+                    "{:>2}. {:<12} {:>7}  wave {:>3}  {}  seed {}\n",
+                    i + 1,
+                    entry.name,
+                    entry.score,
+                    entry.wave,
+                    entry.date,
+                    entry.seed
+                ),
+                20.,
+                if Some(i) == rank {
+                    Color::YELLOW
+                } else {
+                    Color::WHITE
+                },
+            )
+        })
+        .collect()
+}
+
+/// A full-window screen of centered text, one section per `(text, font size, color)`
+fn spawn_screen(commands: &mut Commands, my_assets: &MyAssets, lines: Vec<(String, f32, Color)>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0., 0., 0., 0.8).into(),
+            ..Default::default()
+        })
+        .insert(Name::new("Menu Screen"))
+        .insert(MenuScreen)
+        .with_children(|builder| {
+            builder.spawn_bundle(TextBundle {
+                text: Text {
+                    sections: lines
+                        .into_iter()
+                        .map(|(value, font_size, color)| TextSection {
+                            value,
+                            style: TextStyle {
+                                font: my_assets.font.clone(),
+                                font_size,
+                                color,
+                            },
+                        })
+                        .collect(),
+                    alignment: TextAlignment {
+                        horizontal: HorizontalAlign::Center,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+        });
+}
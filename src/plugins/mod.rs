@@ -1,28 +1,31 @@
-use std::time::Duration;
-
 use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 
+pub mod animation_graph;
+pub mod damage;
 pub mod debug;
+pub mod display;
 pub mod enemy;
+pub mod headless;
+pub mod hud;
+pub mod input;
+pub mod laser;
+pub mod menu;
+pub mod particles;
 pub mod player;
+pub mod projectile;
+pub mod rng;
+pub mod save;
+pub mod score;
+pub mod settings;
+pub mod sound;
+pub mod sprite_animation;
+pub mod status;
 pub mod tilemap;
+pub mod timestep;
 pub mod utils;
 
-#[derive(Component)]
-pub struct AnimationTimer {
-    pub timer: Timer
-}
-
-impl Default for AnimationTimer {
-    fn default() -> Self {
-        Self {
-            timer: Timer::new(Duration::from_millis(100), true)
-        }
-    }
-}
-
 #[derive(Clone, Default, Bundle, LdtkIntCell)]
 pub struct ColliderBundle {
     pub name: Name,
@@ -63,14 +66,25 @@ impl From<IntGridCell> for ColliderBundle {
     fn from(int_grid_cell: IntGridCell) -> ColliderBundle {
         let rotation_constraints = LockedAxes::ROTATION_LOCKED;
 
-        if int_grid_cell.value == 1 {
-            ColliderBundle {
+        match int_grid_cell.value {
+            1 => ColliderBundle {
                 collider: Collider::cuboid(8., 8.),
                 rotation_constraints,
                 ..Default::default()
-            }
-        } else {
-            ColliderBundle::default()
+            },
+            // hazards, water and pits each get a collider of their own
+            2..=4 => ColliderBundle {
+                name: Name::new(match int_grid_cell.value {
+                    2 => "Hazard",
+                    3 => "Water",
+                    _ => "Pit",
+                }),
+                collider: Collider::cuboid(8., 8.),
+                rigid_body: RigidBody::Fixed,
+                rotation_constraints,
+                ..Default::default()
+            },
+            _ => ColliderBundle::default(),
         }
     }
 }
@@ -0,0 +1,292 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use rand::Rng;
+
+use crate::GameState;
+
+use super::timestep::{GameTick, TIMESTEP};
+
+/// Drawn above the map, the player and enemies
+const PARTICLE_Z: f32 = 5.;
+
+/// How an emitter's particles are spawned, look and move
+#[derive(Debug, Clone)]
+pub struct ParticleEffect {
+    /// spawned as soon as the emitter starts
+    pub burst: u32,
+    /// spawned per second after that, until `duration` is up
+    pub rate: f32,
+    /// seconds the emitter lives for, it despawns afterwards, `f32::INFINITY` until it's despawned
+    pub duration: f32,
+    /// seconds each particle lives for
+    pub lifetime: f32,
+    /// particles start somewhere along this many pixels in the emitter's direction
+    pub length: f32,
+    /// lowest and highest starting speed in pixels per second
+    pub speed: (f32, f32),
+    /// half the angle of the cone particles fly out in, around the emitter's direction
+    pub spread: f32,
+    /// color at birth and at death, faded between
+    pub colors: (Color, Color),
+    /// size in pixels at birth and at death
+    pub sizes: (f32, f32),
+    pub gravity: Vec2,
+}
+
+impl Default for ParticleEffect {
+    fn default() -> Self {
+        Self {
+            burst: 0,
+            rate: 0.,
+            duration: 0.,
+            lifetime: 0.3,
+            length: 0.,
+            speed: (50., 100.),
+            spread: PI,
+            colors: (Color::WHITE, Color::rgba(1., 1., 1., 0.)),
+            sizes: (2., 1.),
+            gravity: Vec2::ZERO,
+        }
+    }
+}
+
+impl ParticleEffect {
+    pub fn muzzle_flash() -> Self {
+        Self {
+            burst: 8,
+            lifetime: 0.1,
+            speed: (80., 200.),
+            spread: 0.35,
+            colors: (Color::rgb(1., 0.95, 0.6), Color::rgba(1., 0.5, 0.1, 0.)),
+            sizes: (2., 1.),
+            ..Default::default()
+        }
+    }
+
+    /// Sparks and dust off a wall
+    pub fn impact() -> Self {
+        Self {
+            burst: 10,
+            lifetime: 0.3,
+            speed: (30., 120.),
+            colors: (Color::rgb(0.9, 0.85, 0.7), Color::rgba(0.5, 0.5, 0.5, 0.)),
+            sizes: (2., 1.),
+            gravity: Vec2::new(0., -200.),
+            ..Default::default()
+        }
+    }
+
+    /// Sparks for as long as the laser touches something, moved along with the end of the beam
+    pub fn laser_hit() -> Self {
+        Self {
+            rate: 60.,
+            duration: f32::INFINITY,
+            lifetime: 0.2,
+            speed: (40., 120.),
+            spread: 1.,
+            colors: (Color::rgb(1., 0.6, 0.3), Color::rgba(1., 0.2, 0., 0.)),
+            sizes: (1.5, 0.5),
+            ..Default::default()
+        }
+    }
+
+    pub fn slash() -> Self {
+        Self {
+            burst: 12,
+            lifetime: 0.25,
+            speed: (40., 110.),
+            spread: 0.9,
+            colors: (Color::WHITE, Color::rgba(0.6, 0.8, 1., 0.)),
+            sizes: (2., 0.5),
+            ..Default::default()
+        }
+    }
+
+    pub fn death() -> Self {
+        Self {
+            burst: 30,
+            lifetime: 0.6,
+            speed: (20., 160.),
+            colors: (Color::rgb(0.8, 0.1, 0.1), Color::rgba(0.3, 0., 0., 0.)),
+            sizes: (3., 1.),
+            gravity: Vec2::new(0., -150.),
+            ..Default::default()
+        }
+    }
+
+    /// Laid along the path of a dash, pointing the way it went
+    pub fn dash_trail(length: f32) -> Self {
+        Self {
+            burst: 10,
+            rate: 120.,
+            duration: 0.15,
+            lifetime: 0.3,
+            length,
+            speed: (5., 20.),
+            colors: (Color::rgba(0.7, 0.9, 1., 0.8), Color::rgba(0.7, 0.9, 1., 0.)),
+            sizes: (3., 1.),
+            ..Default::default()
+        }
+    }
+}
+
+/// Spawns particles of its effect where it stands, then despawns once its duration is up
+#[derive(Component)]
+pub struct ParticleEmitter {
+    pub effect: ParticleEffect,
+    /// direction the particles fly out in, and the emitter's length lies along
+    pub direction: Vec2,
+    elapsed: f32,
+    /// particles owed by the rate that haven't been spawned yet
+    pending: f32,
+    started: bool,
+}
+
+impl ParticleEmitter {
+    pub fn new(effect: ParticleEffect, direction: Vec2) -> Self {
+        Self {
+            effect,
+            direction: direction.normalize_or_zero(),
+            elapsed: 0.,
+            pending: 0.,
+            started: false,
+        }
+    }
+
+    /// The emitter as an entity at `position`
+    pub fn bundle(self, position: Vec2) -> ParticleEmitterBundle {
+        ParticleEmitterBundle {
+            emitter: self,
+            transform: Transform::from_translation(position.extend(PARTICLE_Z)),
+            global_transform: GlobalTransform::default(),
+        }
+    }
+}
+
+#[derive(Bundle)]
+pub struct ParticleEmitterBundle {
+    pub emitter: ParticleEmitter,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+}
+
+#[derive(Component)]
+struct Particle {
+    velocity: Vec2,
+    age: f32,
+    lifetime: f32,
+    colors: (Color, Color),
+    sizes: (f32, f32),
+    gravity: Vec2,
+}
+
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        // per frame rather than per tick, particles are only for show
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::emit)
+                .with_system(Self::update_particles)
+                .into(),
+        );
+    }
+}
+
+impl ParticlePlugin {
+    fn emit(
+        mut commands: Commands,
+        mut emitter_query: Query<(Entity, &mut ParticleEmitter, &Transform)>,
+        time: Res<Time>,
+        tick: Res<GameTick>,
+    ) {
+        if tick.paused {
+            return;
+        }
+        // cosmetic, so it doesn't draw from `GameRng` and change how a seeded run plays out
+        let mut rng = rand::thread_rng();
+        for (emitter_e, mut emitter, transform) in emitter_query.iter_mut() {
+            let mut count = 0;
+            if !emitter.started {
+                emitter.started = true;
+                count += emitter.effect.burst;
+            } else {
+                emitter.elapsed += time.delta_seconds();
+                emitter.pending += emitter.effect.rate * time.delta_seconds();
+                count += emitter.pending as u32;
+                emitter.pending = emitter.pending.fract();
+            }
+
+            let effect = &emitter.effect;
+            let base_angle = emitter.direction.y.atan2(emitter.direction.x);
+            for _ in 0..count {
+                let angle = base_angle + rng.gen_range(-effect.spread..=effect.spread);
+                let speed = rng.gen_range(effect.speed.0..=effect.speed.1);
+                let offset = emitter.direction * effect.length * rng.gen::<f32>();
+                commands
+                    .spawn_bundle(SpriteBundle {
+                        sprite: Sprite {
+                            color: effect.colors.0,
+                            custom_size: Some(Vec2::splat(effect.sizes.0)),
+                            ..Default::default()
+                        },
+                        transform: Transform::from_translation(
+                            transform.translation + offset.extend(0.),
+                        ),
+                        ..Default::default()
+                    })
+                    .insert(Particle {
+                        velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+                        age: 0.,
+                        lifetime: effect.lifetime,
+                        colors: effect.colors,
+                        sizes: effect.sizes,
+                        gravity: effect.gravity,
+                    });
+            }
+
+            if emitter.elapsed >= emitter.effect.duration {
+                commands.entity(emitter_e).despawn();
+            }
+        }
+    }
+
+    fn update_particles(
+        mut commands: Commands,
+        mut particle_query: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite)>,
+        time: Res<Time>,
+        tick: Res<GameTick>,
+    ) {
+        if tick.paused {
+            return;
+        }
+        // a long frame shouldn't fling particles across the screen
+        let delta = time.delta_seconds().min(TIMESTEP * 4.);
+        for (particle_e, mut particle, mut transform, mut sprite) in particle_query.iter_mut() {
+            particle.age += delta;
+            if particle.age >= particle.lifetime {
+                commands.entity(particle_e).despawn();
+                continue;
+            }
+            let gravity = particle.gravity;
+            particle.velocity += gravity * delta;
+            transform.translation += (particle.velocity * delta).extend(0.);
+
+            let t = particle.age / particle.lifetime;
+            sprite.color = lerp_color(particle.colors.0, particle.colors.1, t);
+            let size = particle.sizes.0 + (particle.sizes.1 - particle.sizes.0) * t;
+            sprite.custom_size = Some(Vec2::splat(size));
+        }
+    }
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let from = Vec4::from(from.as_rgba_f32());
+    let to = Vec4::from(to.as_rgba_f32());
+    let [r, g, b, a] = from.lerp(to, t).to_array();
+    Color::rgba(r, g, b, a)
+}
@@ -1,15 +1,16 @@
-use std::time::Duration;
-use bevy::{prelude::*, sprite::Anchor};
+use bevy::prelude::*;
 use bevy_ecs_ldtk::prelude::*;
 use bevy_inspector_egui::Inspectable;
 use bevy_rapier2d::prelude::*;
 use iyes_loopless::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::{GameState, MainCamera, MyAssets};
+use crate::{GameState, MyAssets};
 
-use super::{enemy::{Enemy, death}, tilemap::WallCollision, ColliderBundle, utils::{to_world_coordinates, look_at}, AnimationTimer};
+use super::{animation_graph::AnimationTime, enemy::Enemy, tilemap::{nearest_destructible, Destructible, SafeGround, Wading, WallCollision, WATER_SPEED}, ColliderBundle, utils::look_at, particles::{ParticleEffect, ParticleEmitter}, projectile::{Projectile, ProjectileModifiers, ProjectilePool}, sprite_animation::{OnFinish, SpriteAnimation, SpriteClip}, damage::{DamageEvent, DamageKind}, status::StatusKind, rng::GameRng, input::PlayerInput, timestep::{tick_delta, AppGameTickExt, InterpolatedTransform, TickSubstage, TIMESTEP}};
 
-#[derive(Default, Debug, Inspectable)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Inspectable)]
 pub enum Direction {
     #[default]
     None,
@@ -17,33 +18,206 @@ pub enum Direction {
     Left,
     Up,
     Down,
+    UpRight,
+    UpLeft,
+    DownRight,
+    DownLeft,
 }
 
-#[derive(Inspectable)]
+impl Direction {
+    /// The nearest of the eight directions to `vector`
+    pub fn from_vector(vector: Vec2) -> Self {
+        if vector == Vec2::ZERO {
+            return Direction::None;
+        }
+        // eighths of a turn counterclockwise from the right
+        let octant = (vector.y.atan2(vector.x) / std::f32::consts::FRAC_PI_4).round() as i32;
+        match octant.rem_euclid(8) {
+            0 => Direction::Right,
+            1 => Direction::UpRight,
+            2 => Direction::Up,
+            3 => Direction::UpLeft,
+            4 => Direction::Left,
+            5 => Direction::DownLeft,
+            6 => Direction::Down,
+            _ => Direction::DownRight,
+        }
+    }
+
+    /// Unit vector pointing this way, zero for `None`
+    pub fn vector(&self) -> Vec2 {
+        let (x, y) = match self {
+            Direction::None => (0., 0.),
+            Direction::Right => (1., 0.),
+            Direction::Left => (-1., 0.),
+            Direction::Up => (0., 1.),
+            Direction::Down => (0., -1.),
+            Direction::UpRight => (1., 1.),
+            Direction::UpLeft => (-1., 1.),
+            Direction::DownRight => (1., -1.),
+            Direction::DownLeft => (-1., -1.),
+        };
+        Vec2::new(x, y).normalize_or_zero()
+    }
+
+    /// Row of `player_sheet.png` facing this way, and whether it's mirrored to face left
+    fn sheet_row(&self) -> (usize, bool) {
+        match self {
+            Direction::Down | Direction::None => (0, false),
+            Direction::DownRight => (1, false),
+            Direction::DownLeft => (1, true),
+            Direction::Right => (2, false),
+            Direction::Left => (2, true),
+            Direction::UpRight => (3, false),
+            Direction::UpLeft => (3, true),
+            Direction::Up => (4, false),
+        }
+    }
+}
+
+/// What the player is doing, for picking their animation in `player.nodetree`
+///
+/// The variant names are the case names in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Component)]
+pub enum PlayerState {
+    #[default]
+    Idle,
+    Walking,
+    Running,
+    Attacking,
+    /// falling forwards
+    FallF,
+    /// falling backwards
+    FallB,
+    LayingF,
+    LayingB,
+    StandF,
+    StandB,
+}
+
+impl PlayerState {
+    /// Whether `player.nodetree` has frames for this state, the rest show the directional `PlayerBody`
+    pub fn in_nodetree(&self) -> bool {
+        matches!(self, PlayerState::Attacking)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Inspectable, Serialize, Deserialize)]
 pub enum Weapon {
+    #[default]
     Gun,
     Laser,
 }
 
+impl Weapon {
+    /// Damage per shot, or per second for the laser
+    pub fn damage(&self) -> f32 {
+        match self {
+            Weapon::Gun => 5.,
+            Weapon::Laser => 10.,
+        }
+    }
+
+    /// Added on top of the player's own crit chance
+    pub fn crit_chance(&self) -> f32 {
+        match self {
+            Weapon::Gun => 0.05,
+            Weapon::Laser => 0.,
+        }
+    }
+
+    /// How the weapon's projectiles fly before any upgrades
+    pub fn modifiers(&self) -> ProjectileModifiers {
+        match self {
+            Weapon::Gun => ProjectileModifiers {
+                bounces: 1,
+                ..Default::default()
+            },
+            Weapon::Laser => ProjectileModifiers::default(),
+        }
+    }
+}
+
+pub const SLASH_DAMAGE: f32 = 5.;
+const SLASH_HALF_SIZE: f32 = 6.;
+/// Seconds each of the slash's frames is shown for, it lasts as long as its animation
+const SLASH_FRAME_TIME: f32 = 0.1;
+const DASH_SPEED: f32 = 3000.;
+/// Columns of `player_sheet.png`, two idle frames and then four walking ones
+const SHEET_COLUMNS: usize = 6;
+const IDLE_FRAME_TIME: f32 = 0.4;
+const WALK_FRAME_TIME: f32 = 0.12;
+pub const SLASH_CRIT_CHANCE: f32 = 0.1;
+pub const MAX_HP: f32 = 10.;
+/// Laser heat gained per second of firing, it overheats at 1
+const LASER_HEAT_RATE: f32 = 0.25;
+/// Laser heat lost per second while it isn't firing
+const LASER_COOL_RATE: f32 = 0.4;
+
 #[derive(Component, Inspectable)]
 pub struct Player {
     pub hp: f32,
     pub velocity: f32,
+    /// where the player is moving, `None` while standing still
     pub direction: Direction,
+    /// where the player is looking, towards the cursor if there is one
+    pub facing: Direction,
     pub is_moving: bool,
     pub weapon: Weapon,
     pub is_slashing: bool,
+    pub crit_chance: f32,
+    pub crit_multiplier: f32,
+    /// damage is scaled by a random factor within `1 ± damage_variance`
+    pub damage_variance: f32,
+    /// 0 to 1, the laser can't fire while it's overheated
+    pub heat: f32,
+    /// set when the heat reaches 1, until it's cooled all the way down
+    pub overheated: bool,
+    /// added to the modifiers of every weapon's projectiles
+    pub upgrades: ProjectileModifiers,
 }
 
 impl Default for Player {
     fn default() -> Self {
         Self {
-            hp: 10.,
+            hp: MAX_HP,
             direction: Direction::Right,
+            facing: Direction::Down,
             velocity: 200.,
             is_moving: false,
             weapon: Weapon::Gun,
             is_slashing: false,
+            crit_chance: 0.1,
+            crit_multiplier: 2.,
+            damage_variance: 0.2,
+            heat: 0.,
+            overheated: false,
+            upgrades: ProjectileModifiers::default(),
+        }
+    }
+}
+
+impl Player {
+    /// Rolls the final damage of a hit and whether it was a critical hit
+    pub fn roll_damage(&self, base: f32, bonus_crit_chance: f32, rng: &mut impl Rng) -> (f32, bool) {
+        let variance = if self.damage_variance > 0. {
+            rng.gen_range(-self.damage_variance..self.damage_variance)
+        } else {
+            0.
+        };
+        let crit = rng.gen::<f32>() < self.crit_chance + bonus_crit_chance;
+        let multiplier = if crit { self.crit_multiplier } else { 1. };
+        (base * (1. + variance) * multiplier, crit)
+    }
+
+    /// Heats the laser up for a tick of firing, or cools it down
+    pub fn update_heat(&mut self, firing: bool) {
+        if firing {
+            self.heat = (self.heat + LASER_HEAT_RATE * TIMESTEP).min(1.);
+            self.overheated |= self.heat >= 1.;
+        } else {
+            self.heat = (self.heat - LASER_COOL_RATE * TIMESTEP).max(0.);
+            self.overheated &= self.heat > 0.;
         }
     }
 }
@@ -57,6 +231,10 @@ pub struct PlayerBundle {
     #[bundle]
     pub collider_bundle: ColliderBundle,
     pub player: Player,
+    pub interpolated_transform: InterpolatedTransform,
+    pub safe_ground: SafeGround,
+    pub state: PlayerState,
+    pub animation_time: AnimationTime,
     #[worldly]
     pub worldly: Worldly,
     // The whole EntityInstance can be stored directly as an EntityInstance component
@@ -64,12 +242,6 @@ pub struct PlayerBundle {
     pub entity_instance: EntityInstance,
 }
 
-#[derive(Component)]
-pub struct Bullet;
-
-#[derive(Component)]
-pub struct Ray;
-
 #[derive(Component)]
 pub struct Melee;
 
@@ -77,252 +249,240 @@ pub struct Melee;
 pub struct AlreadySlashed;
 
 #[derive(Component)]
-pub struct SlashTimer {
-    pub timer: Timer
-}
-
-impl Default for SlashTimer {
-    fn default() -> Self {
-        Self {
-            timer: Timer::new(Duration::from_millis(500), true) // - 100 for the last index
-        }
-    }
-}
+pub struct Arrow;
 
+/// The player's directional sprite, shown while `player.nodetree` has no frame for them
 #[derive(Component)]
-pub struct Arrow;
+pub struct PlayerBody;
 
 #[derive(Component)]
-struct FlashingTimer {
-    timer: Timer
+pub struct FlashingTimer {
+    pub timer: Timer
 }
 
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
+        app.add_tick_system_set(
+            TickSubstage::Gameplay,
             ConditionSet::new()
                 .run_in_state(GameState::Playing)
                 .with_system(Self::player_movement)
                 .with_system(Self::player_dash)
-                .with_system(Self::player_arrow)
                 .with_system(Self::player_shoot)
                 .with_system(Self::hit)
                 .with_system(Self::flashing)
                 .with_system(Self::switch_weapon)
-                .with_system(Self::animate_slash)
+                .with_system(Self::check_death)
+                .into(),
+        )
+        // after the slash spawned or despawned this tick has actually happened
+        .add_tick_system_set(
+            TickSubstage::Last,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
                 .with_system(Self::check_slash)
+                .with_system(Self::update_state)
+                .into(),
+        )
+        .add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::player_arrow)
+                .with_system(Self::spawn_body)
+                .with_system(Self::animate_body)
                 .into(),
-        );
+        )
+        .add_enter_system(GameState::GameOver, Self::game_over);
     }
 }
 
 impl PlayerPlugin {
-    fn player_movement(
-        mut player_query: Query<(&mut Player, &mut Velocity)>,
-        keyboard: Res<Input<KeyCode>>,
-    ) {
-        for (mut player, mut rb_vels) in player_query.iter_mut() {
-            let up = if keyboard.pressed(KeyCode::W) || keyboard.pressed(KeyCode::Up) {
-                player.direction = Direction::Up;
-                player.is_moving = true;
-                true
-            } else {
-                false
-            };
-            let down = if keyboard.pressed(KeyCode::S) || keyboard.pressed(KeyCode::Down) {
-                player.direction = Direction::Down;
-                player.is_moving = true;
-                true
+    fn update_state(mut player_query: Query<(&Player, &mut PlayerState, &mut AnimationTime)>) {
+        for (player, mut state, mut animation_time) in player_query.iter_mut() {
+            let new_state = if player.is_slashing {
+                PlayerState::Attacking
+            } else if player.is_moving {
+                PlayerState::Walking
             } else {
-                false
+                PlayerState::Idle
             };
-            let left = if keyboard.pressed(KeyCode::A) || keyboard.pressed(KeyCode::Left) {
-                player.direction = Direction::Left;
-                player.is_moving = true;
-                true
+            // the animation restarts on every change
+            if *state != new_state {
+                *state = new_state;
+                animation_time.0 = 0.;
             } else {
-                false
+                animation_time.0 += TIMESTEP;
+            }
+        }
+    }
+
+    fn spawn_body(
+        mut commands: Commands,
+        player_query: Query<Entity, Added<Player>>,
+        my_assets: Res<MyAssets>,
+    ) {
+        for player_e in player_query.iter() {
+            let body = commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: my_assets.player_sheet.clone(),
+                    ..Default::default()
+                })
+                .insert(Name::new("Player Body"))
+                .insert(PlayerBody)
+                .insert(SpriteAnimation::new(SpriteClip::new(0..=1, IDLE_FRAME_TIME)))
+                .id();
+            commands.entity(player_e).add_child(body);
+        }
+    }
+
+    /// Faces the player towards the cursor, or where they're going without one,
+    /// and walks or idles in that direction
+    fn animate_body(
+        mut player_query: Query<(&mut Player, &Transform, &Children)>,
+        mut body_query: Query<(&mut SpriteAnimation, &mut TextureAtlasSprite), With<PlayerBody>>,
+        input: Res<PlayerInput>,
+    ) {
+        for (mut player, player_transform, children) in player_query.iter_mut() {
+            let facing = match input.cursor {
+                Some(world_pos) => {
+                    Direction::from_vector(world_pos - player_transform.translation.truncate())
+                }
+                None => player.direction,
             };
-            let right = if keyboard.pressed(KeyCode::D) || keyboard.pressed(KeyCode::Right) {
-                player.direction = Direction::Right;
-                player.is_moving = true;
-                true
+            // standing still with nothing to look at keeps the last facing
+            if facing != Direction::None && player.facing != facing {
+                player.facing = facing;
+            }
+
+            let (row, flip_x) = player.facing.sheet_row();
+            let first = row * SHEET_COLUMNS;
+            let clip = if player.is_moving {
+                SpriteClip::new(first + 2..=first + 5, WALK_FRAME_TIME)
             } else {
-                false
+                SpriteClip::new(first..=first + 1, IDLE_FRAME_TIME)
             };
-            if !up && !down && !right && !left {
-                player.direction = Direction::None;
-                player.is_moving = false;
+            for &child in children.iter() {
+                let (mut animation, mut sprite) = match body_query.get_mut(child) {
+                    Ok(body) => body,
+                    Err(_) => continue,
+                };
+                animation.play(clip.clone());
+                if sprite.flip_x != flip_x {
+                    sprite.flip_x = flip_x;
+                }
             }
-            let x_axis = -(left as i8) + right as i8;
-            let y_axis = -(down as i8) + up as i8;
-    
+        }
+    }
+
+    fn player_movement(
+        mut player_query: Query<(&mut Player, &mut Velocity, Option<&Wading>)>,
+        input: Res<PlayerInput>,
+    ) {
+        for (mut player, mut rb_vels, wading) in player_query.iter_mut() {
+            let x_axis = -(input.left as i8) + input.right as i8;
+            let y_axis = -(input.down as i8) + input.up as i8;
+
             let mut move_delta = Vec2::new(x_axis as f32, y_axis as f32);
             if move_delta != Vec2::ZERO {
                 move_delta /= move_delta.length();
             }
+            player.direction = Direction::from_vector(move_delta);
+            player.is_moving = player.direction != Direction::None;
     
             // Update the velocity on the rigid_body_component,
             // the bevy_rapier plugin will update the Sprite transform.
-            rb_vels.linvel = move_delta * player.velocity;
+            let speed = if wading.is_some() { WATER_SPEED } else { 1. };
+            rb_vels.linvel = move_delta * player.velocity * speed;
         }
     }
     
     pub fn player_dash(
-        mut player_query: Query<(&Player, &mut Velocity)>,
-        keyboard: Res<Input<KeyCode>>,
+        mut commands: Commands,
+        mut player_query: Query<(&Player, &mut Velocity, &Transform)>,
+        input: Res<PlayerInput>,
     ) {
-        for (player, mut vel) in player_query.iter_mut() {
-            if keyboard.just_pressed(KeyCode::LShift) {
-                let move_dir: Vec2 = match player.direction {
-                    Direction::Right => Vec2::new(1., 0.),
-                    Direction::Left => Vec2::new(-1., 0.),
-                    Direction::Up => Vec2::new(0., 1.),
-                    Direction::Down => Vec2::new(0., -1.),
-                    _ => Vec2::new(0., 0.),
-                };
-                vel.linvel = move_dir * 3000.;
+        for (player, mut vel, transform) in player_query.iter_mut() {
+            if input.dash {
+                vel.linvel = player.direction.vector() * DASH_SPEED;
+                if player.direction != Direction::None {
+                    // the whole dash happens in the tick after this one
+                    commands.spawn_bundle(
+                        ParticleEmitter::new(ParticleEffect::dash_trail(DASH_SPEED * TIMESTEP), vel.linvel)
+                            .bundle(transform.translation.truncate()),
+                    );
+                }
             }
         }
     }
     
     pub fn player_shoot(
         mut player_query: Query<(&mut Player ,Entity, &Transform), Without<Enemy>>,
-        windows: Res<Windows>,
         my_assets: Res<MyAssets>,
-        q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-        mut ray_query: Query<(&mut Transform, Entity, &mut Sprite), (With<Ray>, Without<Player>, Without<Enemy>)>,
-        mut enemy_query: Query<(&mut Enemy, Entity)>,
         mut commands: Commands,
-        mouse: Res<Input<MouseButton>>,
-        keyboard: Res<Input<KeyCode>>,
-        rapier_context: Res<RapierContext>,
-        time: Res<Time>,
+        input: Res<PlayerInput>,
+        mut projectile_pool: ResMut<ProjectilePool>,
     ) {
-        let window = windows.get_primary().unwrap();
-        if let Ok((camera, camera_transform)) = q_camera.get_single() {
-            if let Some(mouse_position) = window.cursor_position() {
-                if let Ok((mut player, player_e, player_transform)) = player_query.get_single_mut() {
-                    match player.weapon {
-                        Weapon::Gun =>{
-                                if mouse.just_pressed(MouseButton::Left) {
+        if let Some(world_pos) = input.cursor {
+            if let Ok((mut player, player_e, player_transform)) = player_query.get_single_mut() {
+                match player.weapon {
+                    Weapon::Gun =>{
+                            if input.fire_pressed {
    
-                                let world_pos = to_world_coordinates(camera, camera_transform, window, mouse_position);
-    
-                                let player_pos = player_transform.translation.truncate();
-                                let target_position = world_pos.truncate() - player_pos;
-                                let bullet_direction = target_position.normalize();
-                                commands
-                                    .spawn_bundle(SpriteBundle {
-                                        texture: my_assets.arrow.clone(),
-                                        ..Default::default()
-                                    })
-                                    .insert(Bullet)
-                                    .insert(Transform::from_translation(player_transform.translation.truncate().extend(1.)))
-                                    .insert(RigidBody::KinematicVelocityBased)
-                                    .insert(Collider::ball(6.))
-                                    .insert(Ccd::enabled())
-                                    .insert(Sensor)
-                                    .insert(Velocity {
-                                        linvel: bullet_direction * 700.,
-                                        ..Default::default()
-                                    });
-                                }
-                            },
-                            Weapon::Laser =>{
-                                if mouse.pressed(MouseButton::Left) {
-                                let ray_origin = player_transform.translation.truncate();
-                                let world_pos = to_world_coordinates(camera, camera_transform, window, mouse_position);
-                                let target_position = world_pos.truncate() - ray_origin;
-                                let ray_dir = target_position.normalize();
-                                let max_toi = 100.0;
-                                let solid = true;
-                                let filter = QueryFilter{
-                                    exclude_collider: Some(player_e),
-                                    ..Default::default()
-                                };
-                                let target_rotation = look_at(target_position);
-                                if let Ok((mut ray_transform, _ray_e, mut ray_sprite)) = ray_query.get_single_mut() {
-                                    if let Some((entity, _toi)) = rapier_context.cast_ray(
-                                        ray_origin, ray_dir, max_toi, solid, filter
-                                    ) {
-                                        let hit_point = ray_origin + ray_dir * _toi;
-                                        
-                                        ray_sprite.custom_size = Some(Vec2::new(1., player_transform.translation.truncate().distance(hit_point)));
-                                        ray_transform.translation = player_transform.translation.truncate().extend(1.);
-                                        ray_transform.rotation = target_rotation;
-            
-                                        for (mut enemy, enemy_e) in enemy_query.iter_mut() {
-                                            if enemy_e == entity {
-                                                // The first collider hit has the entity `entity` and it hit after
-                                                // the ray travelled a distance equal to `ray_dir * toi`.
-                                                enemy.hp -= 10. * time.delta_seconds();
-                                                if death(&enemy){
-                                                    commands.entity(enemy_e).despawn();
-                                                } else {
-                                                    commands.entity(enemy_e).insert(FlashingTimer{
-                                                        timer: Timer::new(Duration::from_millis(50), true),
-                                                    });
-                                                }
-                                            }
-                                        }
-            
-                                    } else {
-                                        ray_sprite.custom_size = Some(Vec2::new(1., max_toi));
-                                        ray_transform.translation = player_transform.translation.truncate().extend(1.);
-                                        ray_transform.rotation = target_rotation;
-                                    }
-                                } else {
-                                    commands
-                                        .spawn_bundle(SpriteBundle{
-                                            sprite: Sprite {
-                                                anchor: Anchor::BottomCenter,
-                                                ..Default::default()
-                                            },
-                                            texture: my_assets.arrow.clone(),
-                                            ..Default::default()
-                                        })
-                                        .insert(Ray);
-                                }
-                            }
-                            if mouse.just_released(MouseButton::Left) {
-                                if let Ok((mut _ray_transform, ray_e, _ray_sprite)) = ray_query.get_single_mut() {
-                                    commands.entity(ray_e).despawn_recursive();
-                                }
+                            let player_pos = player_transform.translation.truncate();
+                            let target_position = world_pos - player_pos;
+                            let bullet_direction = target_position.normalize();
+                            let bullet = Projectile::bullet(player_e)
+                                .with_modifiers(player.weapon.modifiers().combine(player.upgrades));
+                            let muzzle = player_pos + bullet_direction * bullet.radius;
+                            projectile_pool.fire(
+                                &mut commands,
+                                my_assets.arrow.clone(),
+                                bullet,
+                                player_pos.extend(1.),
+                                bullet_direction,
+                            );
+                            commands.spawn_bundle(
+                                ParticleEmitter::new(ParticleEffect::muzzle_flash(), bullet_direction).bundle(muzzle),
+                            );
                             }
                         },
-                    }
-                    if keyboard.just_pressed(KeyCode::F) {
+                    // the laser is fired by `LaserPlugin`
+                    Weapon::Laser => {}
+                }
+                if input.slash {
 
-                        let world_pos = to_world_coordinates(camera, camera_transform, window, mouse_position);
+                    let player_pos = player_transform.translation.truncate();
+                    let target_position = world_pos - player_pos;
+                    let target_rotation = look_at(target_position);
+                    let slash_direction = target_position.normalize().extend(55.) * 11.;
+                    
+                    if player.is_slashing == false {
+                        player.is_slashing = true;
+                        let melee_attack = commands.spawn_bundle(SpriteSheetBundle{
+                            texture_atlas: my_assets.slash.clone(),
+                            transform: Transform{
 
-                        let player_pos = player_transform.translation.truncate();
-                        let target_position = world_pos.truncate() - player_pos;
-                        let target_rotation = look_at(target_position);
-                        let slash_direction = target_position.normalize().extend(55.) * 11.;
-                        
-                        if player.is_slashing == false {
-                            player.is_slashing = true;
-                            let melee_attack = commands.spawn_bundle(SpriteSheetBundle{
-                                texture_atlas: my_assets.slash.clone(),
-                                transform: Transform{
-
-                                    translation: slash_direction,
-                                    rotation: target_rotation,
-                                    ..Default::default()
-                                },
+                                translation: slash_direction,
+                                rotation: target_rotation,
                                 ..Default::default()
-                            })
-                            .insert(Melee)
-                            .insert(SlashTimer::default())
-                            .insert(AnimationTimer::default()).insert(RigidBody::KinematicVelocityBased)
-                            .insert(Collider::cuboid(6., 6.))
-                            .insert(Ccd::enabled())
-                            .insert(Sensor).id();
-                            commands.entity(player_e).add_child(melee_attack);
-                        }
+                            },
+                            ..Default::default()
+                        })
+                        .insert(Melee)
+                        .insert(SpriteAnimation::new(
+                            SpriteClip::new(0..=4, SLASH_FRAME_TIME).once(OnFinish::Despawn),
+                        ))
+                        .insert(RigidBody::KinematicVelocityBased)
+                        .insert(Collider::cuboid(SLASH_HALF_SIZE, SLASH_HALF_SIZE))
+                        .insert(Ccd::enabled())
+                        .insert(Sensor).id();
+                        commands.entity(player_e).add_child(melee_attack);
+                        commands.spawn_bundle(
+                            ParticleEmitter::new(ParticleEffect::slash(), target_position)
+                                .bundle(player_pos + slash_direction.truncate()),
+                        );
                     }
                 }
             }
@@ -333,22 +493,17 @@ impl PlayerPlugin {
         player_query: Query<(&Transform, Entity), (With<Player>, Without<Enemy>)>,
         mut commands: Commands,
         my_assets: Res<MyAssets>,
-        q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
-        windows: Res<Windows>,
+        input: Res<PlayerInput>,
         mut arrow_query: Query<&mut Transform, (With<Arrow>, Without<Player>)>,
     ) {
-        let window = windows.get_primary().unwrap();
         match arrow_query.get_single_mut() {
             Ok(mut arrow_transform) => {
                 for (player_transform, _player_entity) in player_query.iter() {
-                    if let Some(mouse_position) = window.cursor_position() {
-                        if let Ok((camera, camera_transform)) = q_camera.get_single() {
-                            let world_pos = to_world_coordinates(camera, camera_transform, window, mouse_position);
-                            let player_pos = player_transform.translation.truncate();
-                            let target_position = world_pos.truncate() - player_pos;
-                            let arrow_direction = target_position.normalize().extend(55.) * 11.;
-                            arrow_transform.translation = arrow_direction;
-                        }
+                    if let Some(world_pos) = input.cursor {
+                        let player_pos = player_transform.translation.truncate();
+                        let target_position = world_pos - player_pos;
+                        let arrow_direction = target_position.normalize().extend(55.) * 11.;
+                        arrow_transform.translation = arrow_direction;
                     }
                 }
             }
@@ -372,50 +527,62 @@ impl PlayerPlugin {
         }
     }
     
+    /// Slash hits, bullets are handled by `ProjectilePlugin`
     fn hit(
-        mut commands: Commands,
-        mut enemy_query: Query<(&mut Enemy, Entity)>,
+        mut enemy_query: Query<(&mut Enemy, Entity, &Transform)>,
         wall_collision_query: Query<Entity, With<WallCollision>>,
-        bullet_query: Query<Entity, With<Bullet>>,
-        melee_query: Query<Entity, With<Melee>>,
+        mut destructible_query: Query<(Entity, &GlobalTransform, &mut Destructible)>,
+        melee_query: Query<(Entity, &GlobalTransform), With<Melee>>,
+        player_query: Query<&Player>,
         rapier_context: Res<RapierContext>,
+        mut damage_events: EventWriter<DamageEvent>,
+        mut rng: ResMut<GameRng>,
     ) {
-        for bullet_e in bullet_query.iter() {
-            for (collider1, collider2, _intersecting) in rapier_context.intersections_with(bullet_e) {
-                for (mut enemy, enemy_e) in enemy_query.iter_mut() {
+        let player = match player_query.get_single() {
+            Ok(player) => player,
+            Err(_) => return,
+        };
+        for (melee_e, melee_transform) in melee_query.iter() {
+            for (collider1, collider2, _intersecting) in rapier_context.intersections_with(melee_e) {
+                for (mut enemy, enemy_e, enemy_transform) in enemy_query.iter_mut() {
                     if collider1 == enemy_e || collider2 == enemy_e {
-                        enemy.hp -= 5.;
-                        if death(&enemy){
-                            commands.entity(enemy_e).despawn();
-                        } else {
-                            commands.entity(enemy_e).insert(FlashingTimer{
-                                timer: Timer::new(Duration::from_millis(50), true),
+                        if enemy.slashed == false {
+                            enemy.slashed = true;
+                            let (amount, crit) =
+                                player.roll_damage(SLASH_DAMAGE, SLASH_CRIT_CHANCE, &mut rng.crits);
+                            damage_events.send(DamageEvent {
+                                target: enemy_e,
+                                position: enemy_transform.translation,
+                                amount,
+                                kind: DamageKind::Slash,
+                                crit,
+                                status: Some(StatusKind::Poison),
                             });
                         }
-                        commands.entity(bullet_e).despawn_recursive();
                     }
                 }
                 if wall_collision_query.contains(collider1) || wall_collision_query.contains(collider2)
                 {
-                    commands.entity(bullet_e).despawn_recursive();
-                }
-            }
-        }
-        for melee_e in melee_query.iter() {
-            for (collider1, collider2, _intersecting) in rapier_context.intersections_with(melee_e) {
-                for (mut enemy, enemy_e) in enemy_query.iter_mut() {
-                    if collider1 == enemy_e || collider2 == enemy_e {
-                        if enemy.slashed == false {
-                            enemy.hp -= 5.;
-                            if death(&enemy){
-                                commands.entity(enemy_e).despawn();
-                            } else {
-                                enemy.slashed = true;
-                                commands.entity(enemy_e)
-                                .insert(FlashingTimer{
-                                    timer: Timer::new(Duration::from_millis(50), true),
-                                });
-                            }
+                    let destructible = nearest_destructible(
+                        melee_transform.translation.truncate(),
+                        SLASH_HALF_SIZE,
+                        destructible_query.iter().map(|(tile, transform, _)| (tile, transform)),
+                    );
+                    if let Some(Ok((tile, tile_transform, mut destructible))) =
+                        destructible.map(|tile| destructible_query.get_mut(tile))
+                    {
+                        if !destructible.slashed {
+                            destructible.slashed = true;
+                            let (amount, crit) =
+                                player.roll_damage(SLASH_DAMAGE, SLASH_CRIT_CHANCE, &mut rng.crits);
+                            damage_events.send(DamageEvent {
+                                target: tile,
+                                position: tile_transform.translation,
+                                amount,
+                                kind: DamageKind::Slash,
+                                crit,
+                                status: None,
+                            });
                         }
                     }
                 }
@@ -425,70 +592,62 @@ impl PlayerPlugin {
     
     fn flashing (
         mut commands: Commands,
-        mut flashing_query: Query<(&mut FlashingTimer, Entity, &mut Sprite)>,
-        time: Res<Time>,
+        mut flashing_query: Query<(&mut FlashingTimer, Entity)>,
     ) {
-        for (mut timer, timer_e, mut timer_sprite) in flashing_query.iter_mut() {
-            timer_sprite.color = Color::rgba(255., 255., 255., 1.);
-                        
-            timer.timer.tick(time.delta());
+        // the flash color itself is layered with status tints in `StatusPlugin::tint`
+        for (mut timer, timer_e) in flashing_query.iter_mut() {
+            timer.timer.tick(tick_delta());
             
             if timer.timer.finished() {
-                timer_sprite.color = Color::rgba(1.0, 1.0, 1.0, 1.0);
                 commands.entity(timer_e).remove::<FlashingTimer>();
             }
         }
     }
 
     fn switch_weapon(
-        mut commands: Commands,
-        keyboard: Res<Input<KeyCode>>,
+        input: Res<PlayerInput>,
         mut player_query: Query<&mut Player, Without<Enemy>>,
-        ray_query: Query<Entity, (With<Ray>, Without<Player>, Without<Enemy>)>,
     ) {
         if let Ok(mut player) = player_query.get_single_mut() {
-            if keyboard.just_pressed(KeyCode::Key1) {
+            if input.select_gun {
                 player.weapon = Weapon::Gun;
-                if let Ok(ray_e) = ray_query.get_single() {
-                    commands.entity(ray_e).despawn_recursive();
-                }
             }
-            if keyboard.just_pressed(KeyCode::Key2) {
+            if input.select_laser {
                 player.weapon = Weapon::Laser;
             }
         }
     }
 
-    fn check_slash(
-        slash_query: Query<&SlashTimer, With<Melee>>,
-        mut player_query: Query<&mut Player, Without<Enemy>>,
-        mut enemy_query: Query<&mut Enemy>,
+    fn check_death(
+        mut commands: Commands,
+        player_query: Query<&Player>,
     ) {
-        for mut player in player_query.iter_mut() {
-            if let Ok(slash_timer) = slash_query.get_single() {
-                if slash_timer.timer.finished() {
-                    player.is_slashing = false;
-                    for mut enemy in enemy_query.iter_mut() {
-                        enemy.slashed = false;
-                    }
-                }
+        if let Ok(player) = player_query.get_single() {
+            if player.hp <= 0. {
+                commands.insert_resource(NextState(GameState::GameOver));
             }
         }
     }
 
-    fn animate_slash(
-        mut slash_query: Query<(Entity, &mut SlashTimer, &mut AnimationTimer, &mut TextureAtlasSprite), With<Melee>>,
-        time: Res<Time>,
-        mut commands: Commands
+    fn game_over(rng: Res<GameRng>) {
+        info!("Game over! Replay this run with --seed {}", rng.seed);
+    }
+
+    /// Ends the slash once its animation has played out and despawned it
+    fn check_slash(
+        slash_query: Query<(), With<Melee>>,
+        mut player_query: Query<&mut Player, Without<Enemy>>,
+        mut enemy_query: Query<&mut Enemy>,
+        mut destructible_query: Query<&mut Destructible>,
     ) {
-        for (slash_e, mut slash_timer, mut animation_timer, mut texture) in slash_query.iter_mut() {
-            animation_timer.timer.tick(time.delta());
-            slash_timer.timer.tick(time.delta());
-            if animation_timer.timer.finished() {
-                if slash_timer.timer.finished() {
-                    commands.entity(slash_e).despawn_recursive();
-                } else {
-                    texture.index += 1;
+        for mut player in player_query.iter_mut() {
+            if player.is_slashing && slash_query.is_empty() {
+                player.is_slashing = false;
+                for mut enemy in enemy_query.iter_mut() {
+                    enemy.slashed = false;
+                }
+                for mut destructible in destructible_query.iter_mut() {
+                    destructible.slashed = false;
                 }
             }
         }
@@ -0,0 +1,458 @@
+use std::f32::consts::FRAC_PI_3;
+
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+use bevy_rapier2d::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+use super::{
+    damage::{DamageEvent, DamageKind},
+    enemy::Enemy,
+    particles::{ParticleEffect, ParticleEmitter},
+    player::{Player, Weapon},
+    rng::GameRng,
+    status::StatusKind,
+    tilemap::{nearest_destructible, Destructible, WallCollision},
+    timestep::{AppGameTickExt, InterpolatedTransform, TickSubstage, TIMESTEP},
+};
+
+const BULLET_RADIUS: f32 = 6.;
+const BULLET_SPEED: f32 = 700.;
+/// Homing projectiles only steer towards enemies this close
+const HOMING_RANGE: f32 = 200.;
+/// and within this angle either side of where they're flying
+const HOMING_CONE: f32 = FRAC_PI_3;
+/// Radians per second of homing each homing upgrade adds
+const HOMING_UPGRADE: f32 = 2.;
+
+/// Changes to how projectiles fly, from the weapon firing them and the player's upgrades
+#[derive(Debug, Clone, Copy, Default, PartialEq, Inspectable, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectileModifiers {
+    /// enemies passed through before the one that stops it
+    pub pierce: u32,
+    /// walls bounced off before the one that stops it
+    pub bounces: u32,
+    /// radians per second it turns towards an enemy ahead of it, 0 for none
+    pub homing: f32,
+}
+
+impl ProjectileModifiers {
+    /// Both sets of modifiers at once, the strongest homing of the two wins
+    pub fn combine(self, other: Self) -> Self {
+        Self {
+            pierce: self.pierce + other.pierce,
+            bounces: self.bounces + other.bounces,
+            homing: self.homing.max(other.homing),
+        }
+    }
+
+    /// These plus reward `n`, counting from 0, which take turns adding pierce, a bounce and homing
+    pub fn upgraded(self, n: u32) -> Self {
+        match n % 3 {
+            0 => Self {
+                pierce: self.pierce + 1,
+                ..self
+            },
+            1 => Self {
+                bounces: self.bounces + 1,
+                ..self
+            },
+            _ => Self {
+                homing: self.homing + HOMING_UPGRADE,
+                ..self
+            },
+        }
+    }
+}
+
+/// Something fired that flies until it hits, runs out of time or goes too far
+#[derive(Component, Debug, Clone)]
+pub struct Projectile {
+    /// whoever fired it, whose crit chance it rolls with if they're the player
+    pub owner: Entity,
+    pub damage: f32,
+    /// added on top of the owner's crit chance
+    pub crit_chance: f32,
+    pub kind: DamageKind,
+    pub status: Option<StatusKind>,
+    pub radius: f32,
+    pub speed: f32,
+    /// seconds left before it's spent
+    pub lifetime: f32,
+    /// pixels it can travel before it's spent
+    pub max_distance: f32,
+    /// enemies it passes through before the one that stops it
+    pub pierce: u32,
+    /// walls it bounces off before the one that stops it
+    pub bounces: u32,
+    /// radians per second it turns towards an enemy ahead of it
+    pub homing: f32,
+    traveled: f32,
+    /// enemies already hit, so one it's passing through isn't hit again every tick
+    hit: Vec<Entity>,
+}
+
+impl Projectile {
+    /// The gun's bullet
+    pub fn bullet(owner: Entity) -> Self {
+        Self {
+            owner,
+            damage: Weapon::Gun.damage(),
+            crit_chance: Weapon::Gun.crit_chance(),
+            kind: DamageKind::Bullet,
+            status: Some(StatusKind::Slow),
+            radius: BULLET_RADIUS,
+            speed: BULLET_SPEED,
+            lifetime: 2.,
+            max_distance: 1000.,
+            pierce: 0,
+            bounces: 0,
+            homing: 0.,
+            traveled: 0.,
+            hit: Vec::new(),
+        }
+    }
+
+    pub fn with_modifiers(mut self, modifiers: ProjectileModifiers) -> Self {
+        self.pierce = modifiers.pierce;
+        self.bounces = modifiers.bounces;
+        self.homing = modifiers.homing;
+        self
+    }
+
+    /// Turns `velocity` towards the nearest enemy ahead of the projectile that it hasn't hit
+    fn steer(
+        &self,
+        position: Vec2,
+        velocity: Vec2,
+        enemies: impl Iterator<Item = (Entity, Vec2)>,
+    ) -> Vec2 {
+        let direction = velocity.normalize_or_zero();
+        if self.homing <= 0. || direction == Vec2::ZERO {
+            return velocity;
+        }
+        let target = enemies
+            .filter(|(enemy_e, _)| !self.hit.contains(enemy_e))
+            .map(|(_, enemy_position)| enemy_position - position)
+            .filter(|offset| {
+                offset.length() <= HOMING_RANGE
+                    && direction.angle_between(*offset).abs() <= HOMING_CONE
+            })
+            .min_by(|a, b| a.length().total_cmp(&b.length()));
+        let offset = match target {
+            Some(offset) => offset,
+            None => return velocity,
+        };
+        let max_turn = self.homing * TIMESTEP;
+        let (sin, cos) = direction
+            .angle_between(offset)
+            .clamp(-max_turn, max_turn)
+            .sin_cos();
+        Vec2::new(
+            velocity.x * cos - velocity.y * sin,
+            velocity.x * sin + velocity.y * cos,
+        )
+    }
+}
+
+/// Direction out of `wall` from its surface nearest to `position`
+fn wall_normal(rapier_context: &RapierContext, wall: Entity, position: Vec2) -> Option<Vec2> {
+    let filter = QueryFilter {
+        predicate: Some(&|entity| entity == wall),
+        ..Default::default()
+    };
+    let (_, projection) = rapier_context.project_point(position, false, filter)?;
+    let outwards = if projection.is_inside {
+        projection.point - position
+    } else {
+        position - projection.point
+    };
+    let normal = outwards.normalize_or_zero();
+    (normal != Vec2::ZERO).then(|| normal)
+}
+
+/// Spent projectile entities waiting to be fired again
+///
+/// Their colliders are kept and only stop colliding, so firing doesn't spawn
+/// and despawn an entity and a rapier body for every shot.
+#[derive(Default)]
+pub struct ProjectilePool {
+    free: Vec<Entity>,
+}
+
+impl ProjectilePool {
+    /// Fires `projectile` from `position` in `direction`, reusing a spent one if there is one
+    pub fn fire(
+        &mut self,
+        commands: &mut Commands,
+        texture: Handle<Image>,
+        projectile: Projectile,
+        position: Vec3,
+        direction: Vec2,
+    ) -> Entity {
+        let projectile_e = match self.free.pop() {
+            Some(projectile_e) => projectile_e,
+            None => commands
+                .spawn_bundle(SpriteBundle::default())
+                .insert(Name::new("Projectile"))
+                .insert(RigidBody::KinematicVelocityBased)
+                .insert(Ccd::enabled())
+                .insert(Sensor)
+                .id(),
+        };
+        commands
+            .entity(projectile_e)
+            .insert(texture)
+            .insert(Visibility { is_visible: true })
+            // both, so rapier moves the body this tick instead of putting it back where it was spent
+            .insert(Transform::from_translation(position))
+            .insert(GlobalTransform::from_translation(position))
+            .insert(InterpolatedTransform::default())
+            .insert(Velocity {
+                linvel: direction.normalize_or_zero() * projectile.speed,
+                ..Default::default()
+            })
+            .insert(Collider::ball(projectile.radius))
+            .insert(CollisionGroups::default())
+            .insert(projectile);
+        projectile_e
+    }
+
+    fn release(&mut self, commands: &mut Commands, projectile_e: Entity) {
+        commands
+            .entity(projectile_e)
+            .remove::<Projectile>()
+            .insert(Visibility { is_visible: false })
+            .insert(Velocity::default())
+            .insert(CollisionGroups::new(0, 0));
+        self.free.push(projectile_e);
+    }
+}
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ProjectilePool>().add_tick_system_set(
+            TickSubstage::Gameplay,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::update_projectiles)
+                .into(),
+        );
+    }
+}
+
+impl ProjectilePlugin {
+    /// Spends projectiles that ran out of time or range, steers homing ones,
+    /// and hits or bounces off whatever they touch
+    #[allow(clippy::too_many_arguments)]
+    fn update_projectiles(
+        mut commands: Commands,
+        mut pool: ResMut<ProjectilePool>,
+        mut projectile_query: Query<(Entity, &mut Projectile, &Transform, &mut Velocity)>,
+        enemy_query: Query<(Entity, &Transform), With<Enemy>>,
+        wall_collision_query: Query<(), With<WallCollision>>,
+        destructible_query: Query<(Entity, &GlobalTransform), With<Destructible>>,
+        player_query: Query<&Player>,
+        rapier_context: Res<RapierContext>,
+        mut damage_events: EventWriter<DamageEvent>,
+        mut rng: ResMut<GameRng>,
+    ) {
+        for (projectile_e, mut projectile, transform, mut velocity) in projectile_query.iter_mut() {
+            projectile.lifetime -= TIMESTEP;
+            projectile.traveled += velocity.linvel.length() * TIMESTEP;
+            if projectile.lifetime <= 0. || projectile.traveled >= projectile.max_distance {
+                pool.release(&mut commands, projectile_e);
+                continue;
+            }
+
+            let owner = player_query.get(projectile.owner).ok();
+            let mut roll_damage = |damage, crit_chance| match owner {
+                Some(player) => player.roll_damage(damage, crit_chance, &mut rng.crits),
+                None => (damage, false),
+            };
+            let position = transform.translation;
+            let steered = projectile.steer(
+                position.truncate(),
+                velocity.linvel,
+                enemy_query.iter().map(|(enemy_e, enemy_transform)| {
+                    (enemy_e, enemy_transform.translation.truncate())
+                }),
+            );
+            if steered != velocity.linvel {
+                velocity.linvel = steered;
+            }
+            for (collider1, collider2, _intersecting) in
+                rapier_context.intersections_with(projectile_e)
+            {
+                let other = if collider1 == projectile_e {
+                    collider2
+                } else {
+                    collider1
+                };
+
+                if let Ok((_, enemy_transform)) = enemy_query.get(other) {
+                    if projectile.hit.contains(&other) {
+                        continue;
+                    }
+                    let (amount, crit) = roll_damage(projectile.damage, projectile.crit_chance);
+                    damage_events.send(DamageEvent {
+                        target: other,
+                        position: enemy_transform.translation,
+                        amount,
+                        kind: projectile.kind,
+                        crit,
+                        status: projectile.status,
+                    });
+                    projectile.hit.push(other);
+                    if projectile.pierce == 0 {
+                        pool.release(&mut commands, projectile_e);
+                        break;
+                    }
+                    projectile.pierce -= 1;
+                } else if wall_collision_query.contains(other) {
+                    // without a surface to go by, it hit the wall head on
+                    let normal = wall_normal(&rapier_context, other, position.truncate())
+                        .unwrap_or_else(|| -velocity.linvel.normalize_or_zero());
+                    // still inside the wall it just bounced off, on its way out
+                    if velocity.linvel.dot(normal) >= 0. {
+                        continue;
+                    }
+
+                    // walls are merged into big colliders, so the tile that was hit is found by position
+                    let destructible = nearest_destructible(
+                        position.truncate(),
+                        projectile.radius,
+                        destructible_query.iter(),
+                    );
+                    if let Some(Ok((tile, tile_transform))) =
+                        destructible.map(|tile| destructible_query.get(tile))
+                    {
+                        let (amount, crit) = roll_damage(projectile.damage, projectile.crit_chance);
+                        damage_events.send(DamageEvent {
+                            target: tile,
+                            position: tile_transform.translation,
+                            amount,
+                            kind: projectile.kind,
+                            crit,
+                            status: None,
+                        });
+                    }
+                    commands.spawn_bundle(
+                        ParticleEmitter::new(ParticleEffect::impact(), normal)
+                            .bundle(position.truncate()),
+                    );
+                    if projectile.bounces > 0 {
+                        projectile.bounces -= 1;
+                        let linvel = velocity.linvel;
+                        velocity.linvel = linvel - 2. * linvel.dot(normal) * normal;
+                    } else {
+                        pool.release(&mut commands, projectile_e);
+                    }
+                    // touching two wall colliders at once still only counts as one hit
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn homing(homing: f32) -> Projectile {
+        Projectile::bullet(Entity::from_raw(0)).with_modifiers(ProjectileModifiers {
+            homing,
+            ..Default::default()
+        })
+    }
+
+    fn enemy(id: u32, position: Vec2) -> (Entity, Vec2) {
+        (Entity::from_raw(id), position)
+    }
+
+    #[test]
+    fn upgrades_take_turns() {
+        let mut upgrades = ProjectileModifiers::default();
+        let mut history = Vec::new();
+        for reward in 0..6 {
+            upgrades = upgrades.upgraded(reward);
+            history.push((upgrades.pierce, upgrades.bounces, upgrades.homing));
+        }
+        let h = HOMING_UPGRADE;
+        assert_eq!(
+            history,
+            [
+                (1, 0, 0.),
+                (1, 1, 0.),
+                (1, 1, h),
+                (2, 1, h),
+                (2, 2, h),
+                (2, 2, 2. * h),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_homing_flies_straight() {
+        let velocity = Vec2::new(100., 0.);
+        let steered = homing(0.).steer(Vec2::ZERO, velocity, [enemy(1, Vec2::new(50., 10.))].into_iter());
+        assert_eq!(steered, velocity);
+    }
+
+    #[test]
+    fn turns_at_most_homing_per_tick() {
+        let projectile = homing(3.);
+        let velocity = Vec2::new(100., 0.);
+        let steered = projectile.steer(Vec2::ZERO, velocity, [enemy(1, Vec2::new(50., 50.))].into_iter());
+        let turned = velocity.angle_between(steered);
+        assert!((turned - 3. * TIMESTEP).abs() < 1e-5, "turned {}", turned);
+        assert!((steered.length() - velocity.length()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn turns_straight_at_an_enemy_within_reach() {
+        let velocity = Vec2::new(100., 0.);
+        let target = Vec2::new(100., 1.);
+        let steered = homing(100.).steer(Vec2::ZERO, velocity, [enemy(1, target)].into_iter());
+        assert!(steered.angle_between(target).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ignores_enemies_already_hit() {
+        let mut projectile = homing(3.);
+        projectile.hit.push(Entity::from_raw(1));
+        let velocity = Vec2::new(100., 0.);
+        let steered = projectile.steer(Vec2::ZERO, velocity, [enemy(1, Vec2::new(50., 50.))].into_iter());
+        assert_eq!(steered, velocity);
+    }
+
+    #[test]
+    fn ignores_enemies_outside_the_cone_or_range() {
+        let velocity = Vec2::new(100., 0.);
+        let enemies = [
+            // behind
+            enemy(1, Vec2::new(-50., 10.)),
+            // ahead, but too far to the side
+            enemy(2, Vec2::new(10., 50.)),
+            // straight ahead, but too far
+            enemy(3, Vec2::new(HOMING_RANGE + 1., 10.)),
+        ];
+        let steered = homing(3.).steer(Vec2::ZERO, velocity, enemies.into_iter());
+        assert_eq!(steered, velocity);
+    }
+
+    #[test]
+    fn steers_towards_the_nearest_enemy() {
+        let velocity = Vec2::new(100., 0.);
+        let enemies = [enemy(1, Vec2::new(80., 30.)), enemy(2, Vec2::new(40., -10.))];
+        let steered = homing(3.).steer(Vec2::ZERO, velocity, enemies.into_iter());
+        assert!(velocity.angle_between(steered) < 0.);
+    }
+}
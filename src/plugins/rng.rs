@@ -0,0 +1,97 @@
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+/// Environment variable read for a seed when `--seed` isn't passed
+const SEED_ENV: &str = "T5HM_SEED";
+
+/// A single subsystem's share of the run's randomness
+///
+/// Every stream is derived from the same seed, so drawing more numbers in one subsystem
+/// doesn't change what the others roll.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RngStream(ChaCha8Rng);
+
+impl RngStream {
+    fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(stream);
+        Self(rng)
+    }
+}
+
+impl RngCore for RngStream {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+/// The source of all gameplay randomness, so a run can be reproduced from its seed
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GameRng {
+    pub seed: u64,
+    pub spawning: RngStream,
+    /// what enemies drop, nothing drops anything yet
+    pub loot: RngStream,
+    pub crits: RngStream,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            spawning: RngStream::new(seed, 0),
+            loot: RngStream::new(seed, 1),
+            crits: RngStream::new(seed, 2),
+        }
+    }
+
+    /// Seeds from `--seed <seed>`, then the `T5HM_SEED` environment variable,
+    /// falling back to a random seed
+    ///
+    /// A seed that isn't a number is an error rather than a random run,
+    /// since whoever passed it meant to reproduce a particular one.
+    pub fn from_args() -> Result<Self, String> {
+        Ok(Self::new(seed_from_args()?.unwrap_or_else(rand::random)))
+    }
+}
+
+fn parse_seed(seed: &str, source: &str) -> Result<u64, String> {
+    seed.parse().map_err(|_| {
+        format!(
+            "{} should be a whole number from 0 to {}, not {:?}",
+            source,
+            u64::MAX,
+            seed
+        )
+    })
+}
+
+fn seed_from_args() -> Result<Option<u64>, String> {
+    let mut args = std::env::args();
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            let seed = args.next().ok_or("--seed needs a seed after it")?;
+            return parse_seed(&seed, "--seed").map(Some);
+        }
+        if let Some(seed) = arg.strip_prefix("--seed=") {
+            return parse_seed(seed, "--seed").map(Some);
+        }
+    }
+    match std::env::var(SEED_ENV) {
+        Ok(seed) => parse_seed(&seed, SEED_ENV).map(Some),
+        Err(_) => Ok(None),
+    }
+}
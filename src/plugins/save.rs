@@ -0,0 +1,343 @@
+use std::{path::PathBuf, time::Duration};
+
+use bevy::{app::AppExit, prelude::*, window::WindowCloseRequested};
+use bevy_ecs_ldtk::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{GameState, MyAssets};
+
+use super::{
+    enemy::{spawn_enemy, Enemy, EnemySpawnTimer},
+    input::ReplayMode,
+    player::{Player, Weapon},
+    projectile::ProjectileModifiers,
+    rng::GameRng,
+    score::Score,
+    status::{StatusEffect, StatusEffects, StatusKind},
+    tilemap::SafeGround,
+    timestep::{AppGameTickExt, GameTick, InterpolatedTransform, TickSubstage},
+};
+
+/// Bumped whenever `SaveGame` changes in a way `#[serde(default)]` can't cover,
+/// along with a new arm in `SaveGame::migrate` for the old format
+pub const SAVE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerSave {
+    pub hp: f32,
+    pub weapon: Weapon,
+    pub heat: f32,
+    pub overheated: bool,
+    pub upgrades: ProjectileModifiers,
+    pub position: Vec3,
+    pub safe_ground: Vec3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusSave {
+    pub kind: StatusKind,
+    pub stacks: u32,
+    /// seconds until it wears off
+    pub remaining: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnemySave {
+    pub hp: f32,
+    pub velocity: f32,
+    pub position: Vec3,
+    pub status_effects: Vec<StatusSave>,
+}
+
+impl Default for EnemySave {
+    fn default() -> Self {
+        let enemy = Enemy::default();
+        Self {
+            hp: enemy.hp,
+            velocity: enemy.velocity,
+            position: Vec3::ZERO,
+            status_effects: Vec::new(),
+        }
+    }
+}
+
+/// Everything needed to pick a run back up where it was left
+///
+/// Left out on purpose, since they'd be over within a couple of seconds anyway:
+/// projectiles in flight, the laser's beam and burn cooldowns, how far into its
+/// damage interval each status effect is, particles and damage numbers.
+/// The run's input isn't saved either, so a continued run can't be recorded.
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub tick: u64,
+    pub level_uid: i32,
+    pub wave: u32,
+    /// seconds since the last wave
+    pub wave_elapsed: f32,
+    pub score: Score,
+    pub rng: GameRng,
+    pub player: PlayerSave,
+    pub enemies: Vec<EnemySave>,
+}
+
+/// Just enough of a save to know how to read the rest of it
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+impl SaveGame {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("t5hm").join("save.ron"))
+    }
+
+    pub fn exists() -> bool {
+        Self::path().map_or(false, |path| path.exists())
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let path = Self::path().ok_or("no data directory to load from")?;
+        let contents = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let header: SaveHeader = ron::from_str(&contents).map_err(|e| e.to_string())?;
+        Self::migrate(&contents, header.version)
+    }
+
+    /// Reads a save in the format of `version`, upgrading it to the current one
+    fn migrate(contents: &str, version: u32) -> Result<Self, String> {
+        match version {
+            SAVE_VERSION => ron::from_str(contents).map_err(|e| e.to_string()),
+            version if version > SAVE_VERSION => Err(format!(
+                "save version {} is from a newer version of the game",
+                version
+            )),
+            version => Err(format!("save version {} can't be read anymore", version)),
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+        let contents = match ron::ser::to_string_pretty(self, Default::default()) {
+            Ok(contents) => contents,
+            Err(e) => return error!("couldn't serialize the run: {}", e),
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                return error!("couldn't create {}: {}", dir.display(), e);
+            }
+        }
+        if let Err(e) = std::fs::write(&path, contents) {
+            error!("couldn't save the run to {}: {}", path.display(), e);
+        }
+    }
+
+    /// A finished run can't be continued
+    pub fn delete() {
+        if let Some(path) = Self::path().filter(|path| path.exists()) {
+            if let Err(e) = std::fs::remove_file(&path) {
+                error!("couldn't delete the save at {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Puts back everything that isn't an entity, before the level spawns
+    ///
+    /// The player and enemies are put back by `SavePlugin` once the level has spawned.
+    /// A recording or playback is stopped, since a replay can only start from the
+    /// beginning of a run.
+    pub fn continue_run(
+        self,
+        commands: &mut Commands,
+        tick: &mut GameTick,
+        replay_mode: &mut ReplayMode,
+    ) {
+        if !matches!(replay_mode, ReplayMode::Live) {
+            warn!("A continued run can't be recorded or played back, it's being played live");
+            *replay_mode = ReplayMode::Live;
+        }
+        let mut spawn_timer = EnemySpawnTimer {
+            wave: self.wave,
+            ..Default::default()
+        };
+        spawn_timer
+            .timer
+            .set_elapsed(Duration::from_secs_f32(self.wave_elapsed));
+        tick.tick = self.tick;
+
+        commands.insert_resource(spawn_timer);
+        commands.insert_resource(LevelSelection::Uid(self.level_uid));
+        commands.insert_resource(self.score);
+        commands.insert_resource(self.rng);
+        commands.insert_resource(PendingLoad {
+            player: self.player,
+            enemies: self.enemies,
+        });
+    }
+}
+
+/// The entities of a continued run, waiting for the level to spawn
+struct PendingLoad {
+    player: PlayerSave,
+    enemies: Vec<EnemySave>,
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        // on the tick, after interpolated transforms are put back and before rapier syncs bodies
+        app.add_tick_system_set(
+            TickSubstage::Gameplay,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::restore_entities)
+                .into(),
+        )
+        // last, so the exit is seen the same frame it's sent
+            .add_system_to_stage(
+                CoreStage::Last,
+                Self::save_run.run_in_state(GameState::Playing),
+            )
+            .add_enter_system(GameState::GameOver, Self::delete_save);
+    }
+}
+
+impl SavePlugin {
+    /// F5 saves the run, and so does quitting in the middle of one
+    #[allow(clippy::too_many_arguments)]
+    fn save_run(
+        keyboard: Res<Input<KeyCode>>,
+        mut exit_events: EventReader<AppExit>,
+        mut close_events: EventReader<WindowCloseRequested>,
+        player_query: Query<(&Player, &Transform, &SafeGround)>,
+        enemy_query: Query<(&Enemy, &Transform, &StatusEffects)>,
+        tick: Res<GameTick>,
+        level_selection: Res<LevelSelection>,
+        spawn_timer: Res<EnemySpawnTimer>,
+        score: Res<Score>,
+        rng: Res<GameRng>,
+    ) {
+        let exiting = exit_events.iter().next().is_some();
+        // `WindowPlugin` turns this into an `AppExit` in the same frame, unless it's set not to
+        let closing = close_events.iter().next().is_some();
+        if !keyboard.just_pressed(KeyCode::F5) && !exiting && !closing {
+            return;
+        }
+        let (player, player_transform, safe_ground) = match player_query.get_single() {
+            Ok(player) => player,
+            Err(_) => return,
+        };
+        let level_uid = match *level_selection {
+            LevelSelection::Uid(uid) => uid,
+            _ => return,
+        };
+
+        let save = SaveGame {
+            version: SAVE_VERSION,
+            tick: tick.tick,
+            level_uid,
+            wave: spawn_timer.wave,
+            wave_elapsed: spawn_timer.timer.elapsed_secs(),
+            score: score.clone(),
+            rng: rng.clone(),
+            player: PlayerSave {
+                hp: player.hp,
+                weapon: player.weapon,
+                heat: player.heat,
+                overheated: player.overheated,
+                upgrades: player.upgrades,
+                position: player_transform.translation,
+                safe_ground: safe_ground.0,
+            },
+            enemies: enemy_query
+                .iter()
+                .map(|(enemy, enemy_transform, status_effects)| EnemySave {
+                    hp: enemy.hp,
+                    velocity: enemy.velocity,
+                    position: enemy_transform.translation,
+                    status_effects: status_effects
+                        .effects
+                        .iter()
+                        .map(|effect| StatusSave {
+                            kind: effect.kind,
+                            stacks: effect.stacks,
+                            remaining: effect.remaining(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        save.save();
+        info!("Saved the run at tick {}", tick.tick);
+    }
+
+    fn delete_save() {
+        SaveGame::delete();
+    }
+
+    /// Moves the player back and replaces the map's enemies with the saved ones
+    fn restore_entities(
+        mut commands: Commands,
+        pending: Option<Res<PendingLoad>>,
+        mut player_query: Query<(
+            &mut Player,
+            &mut Transform,
+            &mut GlobalTransform,
+            &mut InterpolatedTransform,
+            &mut SafeGround,
+        )>,
+        enemy_query: Query<Entity, With<Enemy>>,
+        my_assets: Res<MyAssets>,
+    ) {
+        let pending = match pending {
+            Some(pending) => pending,
+            None => return,
+        };
+        // the map's enemies spawn along with the player
+        let (mut player, mut transform, mut global_transform, mut interpolated, mut safe_ground) =
+            match player_query.get_single_mut() {
+                Ok(player) => player,
+                Err(_) => return,
+            };
+
+        let saved = &pending.player;
+        player.hp = saved.hp;
+        player.weapon = saved.weapon;
+        player.heat = saved.heat;
+        player.overheated = saved.overheated;
+        player.upgrades = saved.upgrades;
+        transform.translation = saved.position;
+        // rapier moves the body from its `GlobalTransform`
+        global_transform.translation = saved.position;
+        interpolated.teleport(saved.position);
+        safe_ground.0 = saved.safe_ground;
+
+        for enemy_e in enemy_query.iter() {
+            commands.entity(enemy_e).despawn_recursive();
+        }
+        for saved in pending.enemies.iter() {
+            let enemy = Enemy {
+                hp: saved.hp,
+                velocity: saved.velocity,
+                ..Default::default()
+            };
+            let enemy_e = spawn_enemy(&mut commands, &my_assets, saved.position, enemy);
+            let mut status_effects = StatusEffects::default();
+            status_effects.effects = saved
+                .status_effects
+                .iter()
+                .map(|status| {
+                    StatusEffect::with_remaining(status.kind, status.stacks, status.remaining)
+                })
+                .collect();
+            commands.entity(enemy_e).insert(status_effects);
+        }
+        commands.remove_resource::<PendingLoad>();
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+use super::timestep::{AppGameTickExt, TickSubstage, TIMESTEP};
+
+/// Seconds without a kill before the combo multiplier starts dropping
+const COMBO_GRACE: f32 = 2.;
+/// Multiplier lost per second once the grace period is over
+const COMBO_DECAY: f32 = 0.5;
+/// Multiplier gained per kill
+const COMBO_STEP: f32 = 0.25;
+const MAX_MULTIPLIER: f32 = 4.;
+/// Entries kept in the high-score table
+const HIGH_SCORE_COUNT: usize = 10;
+
+/// Points, kills and the kill combo so far this run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Score {
+    pub points: u32,
+    pub kills: u32,
+    /// applied to the points of every kill, grows with each kill and decays without them
+    pub multiplier: f32,
+    /// seconds since the last kill
+    pub since_kill: f32,
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Self {
+            points: 0,
+            kills: 0,
+            multiplier: 1.,
+            since_kill: 0.,
+        }
+    }
+}
+
+impl Score {
+    /// Scores a kill worth `points` before the multiplier, and builds up the combo
+    pub fn add_kill(&mut self, points: u32) {
+        self.points += (points as f32 * self.multiplier).round() as u32;
+        self.kills += 1;
+        self.multiplier = (self.multiplier + COMBO_STEP).min(MAX_MULTIPLIER);
+        self.since_kill = 0.;
+    }
+
+    fn decay(&mut self, delta: f32) {
+        self.since_kill += delta;
+        if self.since_kill > COMBO_GRACE {
+            self.multiplier = (self.multiplier - COMBO_DECAY * delta).max(1.);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighScore {
+    pub name: String,
+    pub score: u32,
+    pub wave: u32,
+    pub seed: u64,
+    /// YYYY-MM-DD
+    pub date: String,
+}
+
+impl HighScore {
+    /// An entry for a run that just ended, by whoever's logged in today
+    pub fn new(score: u32, wave: u32, seed: u64) -> Self {
+        Self {
+            name: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_else(|_| "Player".to_string()),
+            score,
+            wave,
+            seed,
+            date: today(),
+        }
+    }
+}
+
+/// The best runs so far, best first, saved between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighScores {
+    pub entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("t5hm").join("highscores.ron"))
+    }
+
+    /// Reads the saved table, starting a new one if there isn't one
+    pub fn load() -> Self {
+        let contents = match Self::path().and_then(|path| std::fs::read_to_string(path).ok()) {
+            Some(contents) => contents,
+            None => return Self::default(),
+        };
+        ron::from_str(&contents).unwrap_or_else(|e| {
+            // logging isn't set up yet while the app is being built
+            eprintln!("couldn't read the high scores, starting over: {}", e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+        let contents = match ron::ser::to_string_pretty(self, Default::default()) {
+            Ok(contents) => contents,
+            Err(e) => return error!("couldn't serialize the high scores: {}", e),
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                return error!("couldn't create {}: {}", dir.display(), e);
+            }
+        }
+        if let Err(e) = std::fs::write(&path, contents) {
+            error!("couldn't save the high scores to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Adds `entry` if it's good enough for the table, returning its place in it
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        let rank = self
+            .entries
+            .iter()
+            .position(|other| entry.score > other.score)
+            .unwrap_or(self.entries.len());
+        if rank >= HIGH_SCORE_COUNT {
+            return None;
+        }
+        self.entries.insert(rank, entry);
+        self.entries.truncate(HIGH_SCORE_COUNT);
+        Some(rank)
+    }
+}
+
+/// Today's date in UTC as YYYY-MM-DD
+fn today() -> String {
+    date(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() / 86_400) as i64,
+    )
+}
+
+/// The date `days` days after 1970-01-01 as YYYY-MM-DD
+fn date(days: i64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub struct ScorePlugin;
+
+impl Plugin for ScorePlugin {
+    fn build(&self, app: &mut App) {
+        // after this tick's kills, so the combo decays the same way every run
+        app.init_resource::<Score>().add_tick_system_set(
+            TickSubstage::Last,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::decay_combo)
+                .into(),
+        );
+    }
+}
+
+impl ScorePlugin {
+    fn decay_combo(mut score: ResMut<Score>) {
+        score.decay(TIMESTEP);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(score: u32) -> HighScore {
+        HighScore {
+            name: score.to_string(),
+            score,
+            wave: 1,
+            seed: 0,
+            date: date(0),
+        }
+    }
+
+    fn full_table() -> HighScores {
+        HighScores {
+            entries: (1..=HIGH_SCORE_COUNT as u32).rev().map(|i| entry(i * 10)).collect(),
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(-1), "1969-12-31");
+        assert_eq!(date(10_956), "1999-12-31");
+        assert_eq!(date(10_957), "2000-01-01");
+        assert_eq!(date(11_016), "2000-02-29");
+        assert_eq!(date(19_782), "2024-02-29");
+    }
+
+    #[test]
+    fn insert_ranks_by_score() {
+        let mut high_scores = HighScores::default();
+        assert_eq!(high_scores.insert(entry(50)), Some(0));
+        assert_eq!(high_scores.insert(entry(70)), Some(0));
+        assert_eq!(high_scores.insert(entry(60)), Some(1));
+        let scores: Vec<_> = high_scores.entries.iter().map(|entry| entry.score).collect();
+        assert_eq!(scores, [70, 60, 50]);
+    }
+
+    #[test]
+    fn insert_into_full_table() {
+        let mut high_scores = full_table();
+        // lower than everything, or tied with the last place, doesn't make it in
+        assert_eq!(high_scores.insert(entry(5)), None);
+        assert_eq!(high_scores.insert(entry(10)), None);
+        assert_eq!(high_scores.entries.len(), HIGH_SCORE_COUNT);
+
+        assert_eq!(high_scores.insert(entry(55)), Some(5));
+        assert_eq!(high_scores.entries.len(), HIGH_SCORE_COUNT);
+        assert_eq!(high_scores.entries[5].score, 55);
+        assert_eq!(high_scores.entries.last().unwrap().score, 20);
+    }
+
+    #[test]
+    fn ties_go_below_earlier_entries() {
+        let mut high_scores = full_table();
+        let mut tie = entry(50);
+        tie.name = "new".to_string();
+        assert_eq!(high_scores.insert(tie), Some(6));
+        assert_eq!(high_scores.entries[5].name, "50");
+    }
+
+    #[test]
+    fn multiplier_is_capped() {
+        let mut score = Score::default();
+        for _ in 0..100 {
+            score.add_kill(100);
+        }
+        assert_eq!(score.multiplier, MAX_MULTIPLIER);
+        assert_eq!(score.kills, 100);
+    }
+
+    #[test]
+    fn multiplier_decays_to_one_after_the_grace_period() {
+        let mut score = Score::default();
+        score.add_kill(100);
+        score.add_kill(100);
+        let multiplier = score.multiplier;
+
+        score.decay(COMBO_GRACE);
+        assert_eq!(score.multiplier, multiplier);
+
+        for _ in 0..100 {
+            score.decay(1.);
+        }
+        assert_eq!(score.multiplier, 1.);
+    }
+}
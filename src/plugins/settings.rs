@@ -0,0 +1,367 @@
+use std::path::PathBuf;
+
+use bevy::{prelude::*, window::PresentMode, window::WindowMode};
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{GameState, MyAssets};
+
+use super::{display::ViewScaling, input::PendingInput, timestep::GameTick};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// Multiplier on all damage the player takes
+    pub fn damage_taken(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.5,
+            Difficulty::Normal => 1.,
+            Difficulty::Hard => 1.5,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+}
+
+/// Options the player can change, saved between runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub vsync: bool,
+    pub fullscreen: bool,
+    pub view_scaling: ViewScaling,
+    /// 0 to 1, applies to everything
+    pub volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    pub difficulty: Difficulty,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            fullscreen: false,
+            view_scaling: ViewScaling::default(),
+            volume: 0.8,
+            music_volume: 0.6,
+            sfx_volume: 1.,
+            difficulty: Difficulty::default(),
+        }
+    }
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("t5hm").join("settings.ron"))
+    }
+
+    /// Reads the saved settings, falling back to the defaults
+    pub fn load() -> Self {
+        let contents = match Self::path().and_then(|path| std::fs::read_to_string(path).ok()) {
+            Some(contents) => contents,
+            None => return Self::default(),
+        };
+        ron::from_str(&contents).unwrap_or_else(|e| {
+            // logging isn't set up yet while the app is being built
+            eprintln!("couldn't read settings, using the defaults: {}", e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+        let contents = match ron::ser::to_string_pretty(self, Default::default()) {
+            Ok(contents) => contents,
+            Err(e) => return error!("couldn't serialize settings: {}", e),
+        };
+        if let Some(dir) = path.parent() {
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                return error!("couldn't create {}: {}", dir.display(), e);
+            }
+        }
+        if let Err(e) = std::fs::write(&path, contents) {
+            error!("couldn't save settings to {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        if self.vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
+        }
+    }
+
+    /// Volume of the music, including the overall volume
+    pub fn music_gain(&self) -> f32 {
+        self.volume * self.music_volume
+    }
+
+    /// Volume of the sound effects, including the overall volume
+    pub fn sfx_gain(&self) -> f32 {
+        self.volume * self.sfx_volume
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+}
+
+/// Lines of the settings menu, in order
+const MENU_ITEMS: usize = 8;
+const VOLUME_STEP: f32 = 0.1;
+
+/// The option after (or before) `current` in `order`, wrapping around
+fn cycle<T: Copy + PartialEq>(order: &[T], current: T, forward: bool) -> T {
+    let i = order.iter().position(|option| *option == current).unwrap_or(0);
+    let step = if forward { 1 } else { order.len() - 1 };
+    order[(i + step) % order.len()]
+}
+
+fn step_volume(volume: &mut f32, up: bool) {
+    let step = if up { VOLUME_STEP } else { -VOLUME_STEP };
+    // rounded so repeated steps land back on whole percentages
+    *volume = ((*volume + step).clamp(0., 1.) * 10.).round() / 10.;
+}
+
+/// The settings menu, open while it exists
+#[derive(Component)]
+struct SettingsMenu {
+    selected: usize,
+}
+
+/// Pauses or resumes the game, dropping any input waiting for the next tick either way
+fn set_paused(tick: &mut GameTick, pending_input: &mut PendingInput, paused: bool) {
+    tick.paused = paused;
+    pending_input.clear();
+}
+
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Settings>()
+            .add_system(Self::apply_settings)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::toggle_menu)
+                    .with_system(Self::navigate_menu)
+                    .with_system(Self::update_menu_text)
+                    .into(),
+            );
+    }
+}
+
+impl SettingsPlugin {
+    fn apply_settings(
+        settings: Res<Settings>,
+        mut windows: ResMut<Windows>,
+        mut view_scaling: ResMut<ViewScaling>,
+    ) {
+        if !settings.is_changed() {
+            return;
+        }
+        if let Some(window) = windows.get_primary_mut() {
+            if window.present_mode() != settings.present_mode() {
+                window.set_present_mode(settings.present_mode());
+            }
+            if window.mode() != settings.window_mode() {
+                window.set_mode(settings.window_mode());
+            }
+        }
+        *view_scaling = settings.view_scaling;
+    }
+
+    /// Escape opens and closes the menu, the game is paused while it's open
+    fn toggle_menu(
+        mut commands: Commands,
+        keyboard: Res<Input<KeyCode>>,
+        menu_query: Query<Entity, With<SettingsMenu>>,
+        mut tick: ResMut<GameTick>,
+        mut pending_input: ResMut<PendingInput>,
+        my_assets: Res<MyAssets>,
+    ) {
+        if !keyboard.just_pressed(KeyCode::Escape) {
+            return;
+        }
+        match menu_query.get_single() {
+            Ok(menu_e) => {
+                commands.entity(menu_e).despawn_recursive();
+                set_paused(&mut tick, &mut pending_input, false);
+            }
+            Err(_) => {
+                Self::spawn_menu(&mut commands, &my_assets);
+                set_paused(&mut tick, &mut pending_input, true);
+            }
+        }
+    }
+
+    fn spawn_menu(commands: &mut Commands, my_assets: &MyAssets) {
+        let style = TextStyle {
+            font: my_assets.font.clone(),
+            font_size: 28.,
+            color: Color::WHITE,
+        };
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                color: Color::rgba(0., 0., 0., 0.7).into(),
+                ..Default::default()
+            })
+            .insert(Name::new("Settings Menu"))
+            .insert(SettingsMenu { selected: 0 })
+            .with_children(|builder| {
+                builder.spawn_bundle(TextBundle {
+                    text: Text {
+                        sections: (0..MENU_ITEMS)
+                            .map(|_| TextSection {
+                                value: String::new(),
+                                style: style.clone(),
+                            })
+                            .collect(),
+                        alignment: TextAlignment {
+                            horizontal: HorizontalAlign::Center,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                });
+            });
+    }
+
+    /// Up and down pick an option, left, right and enter change it
+    ///
+    /// The player doesn't move meanwhile: no ticks run while the menu is open,
+    /// and `PlayerInputPlugin` ignores the arrow keys until they're let go.
+    fn navigate_menu(
+        mut commands: Commands,
+        keyboard: Res<Input<KeyCode>>,
+        mut menu_query: Query<(Entity, &mut SettingsMenu)>,
+        mut settings: ResMut<Settings>,
+        mut tick: ResMut<GameTick>,
+        mut pending_input: ResMut<PendingInput>,
+    ) {
+        let (menu_e, mut menu) = match menu_query.get_single_mut() {
+            Ok(menu) => menu,
+            Err(_) => return,
+        };
+
+        if keyboard.just_pressed(KeyCode::Up) {
+            menu.selected = (menu.selected + MENU_ITEMS - 1) % MENU_ITEMS;
+        }
+        if keyboard.just_pressed(KeyCode::Down) {
+            menu.selected = (menu.selected + 1) % MENU_ITEMS;
+        }
+
+        let forward = keyboard.any_just_pressed([KeyCode::Right, KeyCode::Return]);
+        let back = keyboard.just_pressed(KeyCode::Left);
+        if !forward && !back {
+            return;
+        }
+
+        let mut changed = settings.clone();
+        match menu.selected {
+            0 => changed.vsync = !changed.vsync,
+            1 => changed.fullscreen = !changed.fullscreen,
+            2 => {
+                changed.view_scaling = cycle(
+                    &[
+                        ViewScaling::Letterbox,
+                        ViewScaling::Extend,
+                        ViewScaling::PixelPerfect,
+                    ],
+                    changed.view_scaling,
+                    forward,
+                )
+            }
+            3 => step_volume(&mut changed.volume, forward),
+            4 => step_volume(&mut changed.music_volume, forward),
+            5 => step_volume(&mut changed.sfx_volume, forward),
+            6 => {
+                changed.difficulty = cycle(
+                    &[Difficulty::Easy, Difficulty::Normal, Difficulty::Hard],
+                    changed.difficulty,
+                    forward,
+                )
+            }
+            _ => {
+                if keyboard.just_pressed(KeyCode::Return) {
+                    commands.entity(menu_e).despawn_recursive();
+                    set_paused(&mut tick, &mut pending_input, false);
+                }
+                return;
+            }
+        }
+
+        if changed != *settings {
+            changed.save();
+            *settings = changed;
+        }
+    }
+
+    fn update_menu_text(
+        menu_query: Query<(&SettingsMenu, &Children)>,
+        settings: Res<Settings>,
+        mut text_query: Query<&mut Text>,
+    ) {
+        let (menu, children) = match menu_query.get_single() {
+            Ok(menu) => menu,
+            Err(_) => return,
+        };
+
+        let on_off = |on| if on { "On" } else { "Off" };
+        let lines = [
+            format!("VSync: {}", on_off(settings.vsync)),
+            format!("Fullscreen: {}", on_off(settings.fullscreen)),
+            format!(
+                "Scaling: {}",
+                match settings.view_scaling {
+                    ViewScaling::Letterbox => "Letterbox",
+                    ViewScaling::Extend => "Extend",
+                    ViewScaling::PixelPerfect => "Pixel perfect",
+                }
+            ),
+            format!("Volume: {:.0}%", settings.volume * 100.),
+            format!("Music: {:.0}%", settings.music_volume * 100.),
+            format!("Effects: {:.0}%", settings.sfx_volume * 100.),
+            format!("Difficulty: {}", settings.difficulty.name()),
+            "Back".to_string(),
+        ];
+
+        for &child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                for (i, (section, line)) in text.sections.iter_mut().zip(lines.iter()).enumerate() {
+                    let selected = i == menu.selected;
+                    section.value = format!("{} {}\n", if selected { ">" } else { " " }, line);
+                    section.style.color = if selected { Color::YELLOW } else { Color::WHITE };
+                }
+            }
+        }
+    }
+}
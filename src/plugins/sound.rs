@@ -0,0 +1,461 @@
+use std::{
+    f32::consts::{FRAC_PI_4, TAU},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::{
+    audio::{play_queued_audio_system, AudioOutput, Decodable},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::HashMap,
+};
+use bevy_ecs_ldtk::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::GameState;
+
+use super::{
+    damage::{DamageEvent, DamageKind},
+    enemy::Enemy,
+    input::PlayerInput,
+    player::{Melee, Player, Weapon},
+    projectile::Projectile,
+    settings::Settings,
+    timestep::GameTick,
+};
+
+const SAMPLE_RATE: u32 = 44_100;
+/// Sounds further than this from the player can't be heard
+const HEARING_DISTANCE: f32 = 600.;
+/// Sounds this far to the side of the player only come out of one speaker
+const PAN_DISTANCE: f32 = 300.;
+/// Contact damage hurts every tick, so the hurt sound is limited to this often
+const HURT_INTERVAL: f32 = 0.3;
+/// Length of one note of the level music in seconds
+const NOTE_LENGTH: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SoundEffect {
+    Shoot,
+    /// loops while the laser is firing
+    LaserHum,
+    Slash,
+    EnemyHit,
+    EnemyDeath,
+    PlayerHurt,
+}
+
+impl SoundEffect {
+    const ALL: [SoundEffect; 6] = [
+        SoundEffect::Shoot,
+        SoundEffect::LaserHum,
+        SoundEffect::Slash,
+        SoundEffect::EnemyHit,
+        SoundEffect::EnemyDeath,
+        SoundEffect::PlayerHurt,
+    ];
+
+    fn synthesize(&self) -> Arc<[f32]> {
+        match self {
+            SoundEffect::Shoot => synth(0.08, |t| {
+                0.3 * square(sweep(t, 900., 300., 0.08)) * (1. - t / 0.08)
+            }),
+            // a whole number of periods of every part, so the loop is seamless
+            SoundEffect::LaserHum => synth(0.5, |t| {
+                let wobble = 0.8 + 0.2 * (TAU * 4. * t).sin();
+                0.25 * (0.5 * saw(100. * t) + 0.3 * (TAU * 200. * t).sin()) * wobble
+            }),
+            SoundEffect::Slash => synth(0.15, |t| 0.5 * noise(t) * (1. - t / 0.15).powi(2)),
+            SoundEffect::EnemyHit => synth(0.07, |t| {
+                (0.5 * (TAU * 330. * t).sin() + 0.1 * noise(t)) * (-t * 40.).exp()
+            }),
+            SoundEffect::EnemyDeath => synth(0.35, |t| {
+                (0.3 * square(sweep(t, 400., 60., 0.35)) + 0.15 * noise(t)) * (1. - t / 0.35)
+            }),
+            SoundEffect::PlayerHurt => synth(0.2, |t| {
+                0.6 * sweep(t, 220., 90., 0.2).sin() * (1. - t / 0.2)
+            }),
+        }
+    }
+}
+
+/// Samples of every sound effect, synthesized on startup
+pub struct SoundBank {
+    effects: HashMap<SoundEffect, Arc<[f32]>>,
+    /// level music, by level uid
+    music: HashMap<i32, Arc<[f32]>>,
+}
+
+impl Default for SoundBank {
+    fn default() -> Self {
+        Self {
+            effects: SoundEffect::ALL
+                .iter()
+                .map(|effect| (*effect, effect.synthesize()))
+                .collect(),
+            music: HashMap::default(),
+        }
+    }
+}
+
+impl SoundBank {
+    fn effect(&self, effect: SoundEffect) -> Arc<[f32]> {
+        self.effects[&effect].clone()
+    }
+
+    fn music(&mut self, level_uid: i32) -> Arc<[f32]> {
+        self.music
+            .entry(level_uid)
+            .or_insert_with(|| level_music(level_uid))
+            .clone()
+    }
+}
+
+/// Lets the game turn a playing sound up or down, or stop it
+struct SoundControl {
+    /// bits of an `f32`
+    volume: AtomicU32,
+    stopped: AtomicBool,
+}
+
+impl SoundControl {
+    fn new(volume: f32) -> Arc<Self> {
+        Arc::new(Self {
+            volume: AtomicU32::new(volume.to_bits()),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Mono samples played in stereo, panned with a separate gain per speaker
+///
+/// bevy's own `AudioSource` can't be panned, so sounds are played through this instead.
+#[derive(TypeUuid)]
+#[uuid = "3f0a1c52-8d4e-4b7a-9c61-2e5d7b9a0f14"]
+pub struct Sound {
+    samples: Arc<[f32]>,
+    /// left, right
+    gains: [f32; 2],
+    looping: bool,
+    control: Arc<SoundControl>,
+}
+
+impl Decodable for Sound {
+    type Decoder = SoundDecoder;
+    type DecoderItem = f32;
+
+    fn decoder(&self) -> Self::Decoder {
+        SoundDecoder {
+            samples: self.samples.clone(),
+            gains: self.gains,
+            looping: self.looping,
+            control: self.control.clone(),
+            position: 0,
+        }
+    }
+}
+
+pub struct SoundDecoder {
+    samples: Arc<[f32]>,
+    gains: [f32; 2],
+    looping: bool,
+    control: Arc<SoundControl>,
+    /// index into the interleaved stereo output
+    position: usize,
+}
+
+impl Iterator for SoundDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.control.stopped.load(Ordering::Relaxed) || self.samples.is_empty() {
+            return None;
+        }
+        if self.position / 2 >= self.samples.len() {
+            if !self.looping {
+                return None;
+            }
+            self.position = 0;
+        }
+        let sample = self.samples[self.position / 2] * self.gains[self.position % 2];
+        self.position += 1;
+        Some(sample * self.control.volume())
+    }
+}
+
+impl rodio::Source for SoundDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// A sound that keeps playing until it's dropped
+struct Loop(Arc<SoundControl>);
+
+impl Drop for Loop {
+    fn drop(&mut self) {
+        self.0.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Loops {
+    /// the level the music is for, and the music
+    music: Option<(i32, Loop)>,
+    laser_hum: Option<Loop>,
+}
+
+/// When the player was last hurt, to notice the next time they are
+#[derive(Default)]
+struct HurtTracker {
+    hp: Option<f32>,
+    since_sound: f32,
+}
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        // the same setup bevy's own audio plugin does for `AudioSource`
+        app.add_asset::<Sound>()
+            .init_resource::<Audio<Sound>>()
+            .init_non_send_resource::<AudioOutput<Sound>>()
+            .add_system_to_stage(CoreStage::PostUpdate, play_queued_audio_system::<Sound>)
+            .init_resource::<SoundBank>()
+            .init_resource::<Loops>()
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::sound_effects)
+                    .with_system(Self::laser_hum)
+                    .with_system(Self::level_music)
+                    .into(),
+            )
+            .add_exit_system(GameState::Playing, Self::stop_loops);
+    }
+}
+
+impl SoundPlugin {
+    #[allow(clippy::too_many_arguments)]
+    fn sound_effects(
+        audio: Res<Audio<Sound>>,
+        mut sounds: ResMut<Assets<Sound>>,
+        bank: Res<SoundBank>,
+        settings: Res<Settings>,
+        time: Res<Time>,
+        player_query: Query<(&Player, &Transform)>,
+        bullet_query: Query<&Transform, Added<Projectile>>,
+        slash_query: Query<(), Added<Melee>>,
+        enemy_query: Query<(Entity, &Transform), With<Enemy>>,
+        removed_enemies: RemovedComponents<Enemy>,
+        mut damage_events: EventReader<DamageEvent>,
+        mut enemy_positions: Local<HashMap<Entity, Vec3>>,
+        mut hurt: Local<HurtTracker>,
+    ) {
+        let (player, listener) = match player_query.get_single() {
+            Ok((player, transform)) => (player, transform.translation),
+            Err(_) => return,
+        };
+        let mut play = |effect: SoundEffect, position: Vec3| {
+            audio.play(sounds.add(Sound {
+                samples: bank.effect(effect),
+                gains: spatial_gains(position, listener),
+                looping: false,
+                control: SoundControl::new(settings.sfx_gain()),
+            }));
+        };
+
+        for bullet_transform in bullet_query.iter() {
+            play(SoundEffect::Shoot, bullet_transform.translation);
+        }
+        if !slash_query.is_empty() {
+            play(SoundEffect::Slash, listener);
+        }
+        for event in damage_events.iter() {
+            // the laser has its own hum, and hazards aren't the player's doing
+            if matches!(event.kind, DamageKind::Bullet | DamageKind::Slash) {
+                play(SoundEffect::EnemyHit, event.position);
+            }
+        }
+
+        // dead enemies are gone by now, so they die where they were last seen
+        for enemy_e in removed_enemies.iter() {
+            if let Some(position) = enemy_positions.remove(&enemy_e) {
+                play(SoundEffect::EnemyDeath, position);
+            }
+        }
+        for (enemy_e, enemy_transform) in enemy_query.iter() {
+            enemy_positions.insert(enemy_e, enemy_transform.translation);
+        }
+
+        hurt.since_sound += time.delta_seconds();
+        if let Some(hp) = hurt.hp {
+            if player.hp < hp && hurt.since_sound >= HURT_INTERVAL {
+                play(SoundEffect::PlayerHurt, listener);
+                hurt.since_sound = 0.;
+            }
+        }
+        hurt.hp = Some(player.hp);
+    }
+
+    fn laser_hum(
+        audio: Res<Audio<Sound>>,
+        mut sounds: ResMut<Assets<Sound>>,
+        bank: Res<SoundBank>,
+        settings: Res<Settings>,
+        mut loops: ResMut<Loops>,
+        player_query: Query<&Player>,
+        input: Res<PlayerInput>,
+        tick: Res<GameTick>,
+    ) {
+        let firing = !tick.paused
+            && input.fire
+            && player_query
+                .get_single()
+                .map_or(false, |player| {
+                    matches!(player.weapon, Weapon::Laser) && !player.overheated
+                });
+
+        if !firing {
+            // dropping the hum stops it
+            loops.laser_hum = None;
+            return;
+        }
+        if let Some(hum) = &loops.laser_hum {
+            hum.0.set_volume(settings.sfx_gain());
+            return;
+        }
+
+        let control = SoundControl::new(settings.sfx_gain());
+        audio.play(sounds.add(Sound {
+            samples: bank.effect(SoundEffect::LaserHum),
+            // the laser comes from the player, in the middle
+            gains: spatial_gains(Vec3::ZERO, Vec3::ZERO),
+            looping: true,
+            control: control.clone(),
+        }));
+        loops.laser_hum = Some(Loop(control));
+    }
+
+    fn level_music(
+        audio: Res<Audio<Sound>>,
+        mut sounds: ResMut<Assets<Sound>>,
+        mut bank: ResMut<SoundBank>,
+        settings: Res<Settings>,
+        mut loops: ResMut<Loops>,
+        level_selection: Res<LevelSelection>,
+    ) {
+        let level_uid = match *level_selection {
+            LevelSelection::Uid(uid) => uid,
+            _ => return,
+        };
+
+        if let Some((playing_uid, music)) = &loops.music {
+            if *playing_uid == level_uid {
+                music.0.set_volume(settings.music_gain());
+                return;
+            }
+        }
+
+        let control = SoundControl::new(settings.music_gain());
+        audio.play(sounds.add(Sound {
+            samples: bank.music(level_uid),
+            gains: [1., 1.],
+            looping: true,
+            control: control.clone(),
+        }));
+        // replacing the old music stops it
+        loops.music = Some((level_uid, Loop(control)));
+    }
+
+    fn stop_loops(mut loops: ResMut<Loops>) {
+        *loops = Loops::default();
+    }
+}
+
+/// Per-speaker gains for a sound at `position`, heard by the player at `listener`
+fn spatial_gains(position: Vec3, listener: Vec3) -> [f32; 2] {
+    let offset = (position - listener).truncate();
+    let attenuation = (1. - offset.length() / HEARING_DISTANCE).max(0.);
+    let pan = (offset.x / PAN_DISTANCE).clamp(-1., 1.);
+    // equal power, so sounds don't dip in volume as they cross the middle
+    let angle = (pan + 1.) * FRAC_PI_4;
+    [angle.cos() * attenuation, angle.sin() * attenuation]
+}
+
+/// Samples `seconds` of `wave`, a function of time in seconds
+fn synth(seconds: f32, wave: impl Fn(f32) -> f32) -> Arc<[f32]> {
+    let len = (seconds * SAMPLE_RATE as f32) as usize;
+    (0..len)
+        .map(|i| wave(i as f32 / SAMPLE_RATE as f32))
+        .collect()
+}
+
+/// Phase in radians of a tone sliding from `from` to `to` hertz over `duration`
+fn sweep(t: f32, from: f32, to: f32, duration: f32) -> f32 {
+    TAU * (from * t + (to - from) / (2. * duration) * t * t)
+}
+
+fn square(phase: f32) -> f32 {
+    phase.sin().signum()
+}
+
+/// Sawtooth wave with a period of 1
+fn saw(x: f32) -> f32 {
+    2. * (x - (x + 0.5).floor())
+}
+
+/// White noise that's the same every time, so it doesn't touch the game's rng
+fn noise(t: f32) -> f32 {
+    let mut x = ((t * SAMPLE_RATE as f32) as u32).wrapping_mul(0x9e37_79b9) ^ 0x85eb_ca6b;
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x2c1b_3c6d);
+    x ^= x >> 12;
+    x as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+/// A short arpeggio over a bass note, different for each level
+fn level_music(level_uid: i32) -> Arc<[f32]> {
+    const ROOTS: [f32; 4] = [220., 196., 174.61, 246.94];
+    const ARPEGGIOS: [[i32; 8]; 2] = [[0, 4, 7, 12, 7, 4, 0, -5], [0, 3, 7, 10, 12, 10, 7, 3]];
+    let bar = NOTE_LENGTH * 8.;
+
+    let i = level_uid.rem_euclid(ROOTS.len() as i32) as usize;
+    let root = ROOTS[i];
+    let arpeggio = ARPEGGIOS[i % ARPEGGIOS.len()];
+    synth(bar * 2., |t| {
+        // every note and bar starts from silence, so the loop doesn't click
+        let note_t = t % NOTE_LENGTH;
+        let step = (t / NOTE_LENGTH) as usize % arpeggio.len();
+        let freq = root * 2f32.powf(arpeggio[step] as f32 / 12.);
+        let note = (TAU * freq * note_t).sin() * (1. - note_t / NOTE_LENGTH).powi(2);
+
+        let bar_t = t % bar;
+        let bass = (TAU * root / 2. * bar_t).sin() * (1. - bar_t / bar);
+        0.2 * note + 0.15 * bass
+    })
+}
@@ -0,0 +1,325 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+
+use crate::GameState;
+
+use super::timestep::{AppGameTickExt, TickSubstage, TIMESTEP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackMode {
+    Loop,
+    /// plays through once and then finishes on the last frame
+    Once,
+    /// plays forwards then backwards, forever
+    PingPong,
+}
+
+/// What happens when a `PlaybackMode::Once` clip reaches its end
+#[derive(Debug, Clone)]
+pub enum OnFinish {
+    /// stays on the last frame
+    Hold,
+    Despawn,
+    /// sends an `AnimationFinished` for the entity
+    Event,
+    /// starts playing another clip
+    Switch(Box<SpriteClip>),
+}
+
+/// A run of frames in a texture atlas and how to play them
+#[derive(Debug, Clone)]
+pub struct SpriteClip {
+    /// atlas indices, in order
+    pub frames: RangeInclusive<usize>,
+    /// seconds each frame is shown for, all above zero,
+    /// the last one is used for any frames past the end
+    pub durations: Vec<f32>,
+    pub mode: PlaybackMode,
+    pub on_finish: OnFinish,
+}
+
+impl SpriteClip {
+    /// A looping clip showing every frame for `frame_time` seconds
+    pub fn new(frames: RangeInclusive<usize>, frame_time: f32) -> Self {
+        assert_durations(&[frame_time]);
+        Self {
+            frames,
+            durations: vec![frame_time],
+            mode: PlaybackMode::Loop,
+            on_finish: OnFinish::Hold,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: PlaybackMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_durations(mut self, durations: Vec<f32>) -> Self {
+        assert_durations(&durations);
+        self.durations = durations;
+        self
+    }
+
+    /// Plays once and then does `on_finish`
+    pub fn once(mut self, on_finish: OnFinish) -> Self {
+        self.mode = PlaybackMode::Once;
+        self.on_finish = on_finish;
+        self
+    }
+
+    fn len(&self) -> usize {
+        self.frames.end().saturating_sub(*self.frames.start()) + 1
+    }
+
+    fn duration(&self, frame: usize) -> f32 {
+        self.durations
+            .get(frame)
+            .or_else(|| self.durations.last())
+            .copied()
+            .unwrap_or(f32::INFINITY)
+    }
+}
+
+/// A frame shown for no time at all would never let `advance` finish
+fn assert_durations(durations: &[f32]) {
+    assert!(
+        durations.iter().all(|duration| *duration > 0.),
+        "frame durations must be above zero, got {:?}",
+        durations
+    );
+}
+
+/// Plays a `SpriteClip` on the entity's `TextureAtlasSprite`
+#[derive(Component, Debug, Clone)]
+pub struct SpriteAnimation {
+    pub clip: SpriteClip,
+    /// position in the clip, not the atlas index
+    frame: usize,
+    elapsed: f32,
+    backwards: bool,
+    finished: bool,
+}
+
+impl SpriteAnimation {
+    pub fn new(clip: SpriteClip) -> Self {
+        Self {
+            clip,
+            frame: 0,
+            elapsed: 0.,
+            backwards: false,
+            finished: false,
+        }
+    }
+
+    /// Starts `clip` from its first frame, unless it's already the one playing
+    pub fn play(&mut self, clip: SpriteClip) {
+        if self.clip.frames != clip.frames || self.clip.mode != clip.mode {
+            *self = Self::new(clip);
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn atlas_index(&self) -> usize {
+        self.clip.frames.start() + self.frame
+    }
+
+    /// Moves on by `delta` seconds, returning whether a `Once` clip just ended
+    fn advance(&mut self, delta: f32) -> bool {
+        if self.finished {
+            return false;
+        }
+        self.elapsed += delta;
+        let last = self.clip.len() - 1;
+        while self.elapsed >= self.clip.duration(self.frame) {
+            self.elapsed -= self.clip.duration(self.frame);
+            match self.clip.mode {
+                PlaybackMode::Loop => self.frame = (self.frame + 1) % (last + 1),
+                PlaybackMode::Once => {
+                    if self.frame == last {
+                        self.finished = true;
+                        return true;
+                    }
+                    self.frame += 1;
+                }
+                PlaybackMode::PingPong if last == 0 => {}
+                PlaybackMode::PingPong => {
+                    if self.frame == last {
+                        self.backwards = true;
+                    } else if self.frame == 0 {
+                        self.backwards = false;
+                    }
+                    self.frame = if self.backwards {
+                        self.frame - 1
+                    } else {
+                        self.frame + 1
+                    };
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Sent when a clip with `OnFinish::Event` ends
+pub struct AnimationFinished {
+    pub entity: Entity,
+}
+
+pub struct SpriteAnimationPlugin;
+
+impl Plugin for SpriteAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        // on the tick, so how long an attack's animation lasts is part of the simulation
+        app.add_event::<AnimationFinished>().add_tick_system_set(
+            TickSubstage::Gameplay,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::animate)
+                .into(),
+        );
+    }
+}
+
+impl SpriteAnimationPlugin {
+    fn animate(
+        mut commands: Commands,
+        mut animation_query: Query<(Entity, &mut SpriteAnimation, &mut TextureAtlasSprite)>,
+        mut finished_events: EventWriter<AnimationFinished>,
+    ) {
+        for (entity, mut animation, mut sprite) in animation_query.iter_mut() {
+            if animation.advance(TIMESTEP) {
+                match animation.clip.on_finish.clone() {
+                    OnFinish::Hold => {}
+                    OnFinish::Despawn => commands.entity(entity).despawn_recursive(),
+                    OnFinish::Event => finished_events.send(AnimationFinished { entity }),
+                    OnFinish::Switch(clip) => *animation = SpriteAnimation::new(*clip),
+                }
+            }
+            if sprite.index != animation.atlas_index() {
+                sprite.index = animation.atlas_index();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The atlas index shown after each of `steps` seconds
+    fn play(clip: SpriteClip, steps: usize) -> Vec<usize> {
+        let mut animation = SpriteAnimation::new(clip);
+        (0..steps)
+            .map(|_| {
+                animation.advance(1.);
+                animation.atlas_index()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        assert_eq!(play(SpriteClip::new(2..=4, 1.), 4), [3, 4, 2, 3]);
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let clip = SpriteClip::new(2..=4, 1.).once(OnFinish::Hold);
+        assert_eq!(play(clip.clone(), 4), [3, 4, 4, 4]);
+
+        let mut animation = SpriteAnimation::new(clip);
+        let ended: Vec<_> = (0..4).map(|_| animation.advance(1.)).collect();
+        // only the step that reaches the end reports it
+        assert_eq!(ended, [false, false, true, false]);
+        assert!(animation.finished());
+    }
+
+    #[test]
+    fn ping_pong_turns_around_at_both_ends() {
+        let clip = SpriteClip::new(0..=2, 1.).with_mode(PlaybackMode::PingPong);
+        assert_eq!(play(clip, 6), [1, 2, 1, 0, 1, 2]);
+
+        let single = SpriteClip::new(3..=3, 1.).with_mode(PlaybackMode::PingPong);
+        assert_eq!(play(single, 3), [3, 3, 3]);
+    }
+
+    #[test]
+    fn durations_per_frame() {
+        let clip = SpriteClip::new(0..=2, 1.).with_durations(vec![1., 2.]);
+        // the last duration is used for the frames past the end of the list
+        assert_eq!(play(clip, 6), [1, 1, 2, 2, 0, 1]);
+    }
+
+    #[test]
+    fn a_long_step_skips_frames() {
+        let mut animation = SpriteAnimation::new(SpriteClip::new(0..=3, 1.));
+        animation.advance(2.5);
+        assert_eq!(animation.atlas_index(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_durations_are_rejected() {
+        SpriteClip::new(0..=1, 1.).with_durations(vec![1., 0.]);
+    }
+
+    #[test]
+    fn playing_the_same_clip_doesnt_restart_it() {
+        let mut animation = SpriteAnimation::new(SpriteClip::new(0..=3, 1.));
+        animation.advance(1.);
+        animation.play(SpriteClip::new(0..=3, 1.));
+        assert_eq!(animation.atlas_index(), 1);
+        animation.play(SpriteClip::new(4..=5, 1.));
+        assert_eq!(animation.atlas_index(), 4);
+    }
+
+    /// Runs `animate` once on a single-frame clip that ends with `on_finish`
+    fn finish(on_finish: OnFinish) -> (World, Entity) {
+        let mut world = World::new();
+        world.init_resource::<Events<AnimationFinished>>();
+        let entity = world
+            .spawn()
+            .insert(SpriteAnimation::new(
+                SpriteClip::new(0..=0, TIMESTEP).once(on_finish),
+            ))
+            .insert(TextureAtlasSprite::default())
+            .id();
+        SystemStage::single(SpriteAnimationPlugin::animate).run(&mut world);
+        (world, entity)
+    }
+
+    #[test]
+    fn on_finish_hold() {
+        let (world, entity) = finish(OnFinish::Hold);
+        assert!(world.get::<SpriteAnimation>(entity).unwrap().finished());
+    }
+
+    #[test]
+    fn on_finish_despawn() {
+        let (world, entity) = finish(OnFinish::Despawn);
+        assert!(world.get_entity(entity).is_none());
+    }
+
+    #[test]
+    fn on_finish_event() {
+        let (world, entity) = finish(OnFinish::Event);
+        let events = world.resource::<Events<AnimationFinished>>();
+        let mut reader = events.get_reader();
+        assert!(reader.iter(events).any(|event| event.entity == entity));
+    }
+
+    #[test]
+    fn on_finish_switch() {
+        let (world, entity) = finish(OnFinish::Switch(Box::new(SpriteClip::new(3..=5, 1.))));
+        let animation = world.get::<SpriteAnimation>(entity).unwrap();
+        assert!(!animation.finished());
+        assert_eq!(animation.atlas_index(), 3);
+        assert_eq!(world.get::<TextureAtlasSprite>(entity).unwrap().index, 3);
+    }
+}
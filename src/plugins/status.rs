@@ -0,0 +1,253 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::GameState;
+
+use super::{
+    damage::{DamageEvent, DamageKind},
+    player::FlashingTimer,
+    timestep::{tick_delta, AppGameTickExt, TickSubstage},
+};
+
+/// How often damage over time effects deal their damage
+const TICK_INTERVAL: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StatusKind {
+    Burning,
+    Slow,
+    Freeze,
+    Poison,
+}
+
+impl StatusKind {
+    fn max_stacks(&self) -> u32 {
+        match self {
+            StatusKind::Burning => 3,
+            StatusKind::Slow => 3,
+            StatusKind::Freeze => 1,
+            StatusKind::Poison => 5,
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        match self {
+            StatusKind::Burning => 2.,
+            StatusKind::Slow => 1.5,
+            StatusKind::Freeze => 1.,
+            StatusKind::Poison => 4.,
+        }
+    }
+
+    /// Damage per second for a single stack
+    fn damage_per_second(&self) -> f32 {
+        match self {
+            StatusKind::Burning => 2.,
+            StatusKind::Poison => 1.,
+            _ => 0.,
+        }
+    }
+
+    fn speed_multiplier(&self, stacks: u32) -> f32 {
+        match self {
+            StatusKind::Slow => 0.7_f32.powi(stacks as i32),
+            StatusKind::Freeze => 0.,
+            _ => 1.,
+        }
+    }
+
+    fn tint(&self) -> Color {
+        match self {
+            StatusKind::Burning => Color::rgb(1., 0.55, 0.35),
+            StatusKind::Slow => Color::rgb(0.65, 0.75, 1.),
+            StatusKind::Freeze => Color::rgb(0.5, 0.9, 1.),
+            StatusKind::Poison => Color::rgb(0.55, 1., 0.45),
+        }
+    }
+
+    fn damage_kind(&self) -> Option<DamageKind> {
+        match self {
+            StatusKind::Burning => Some(DamageKind::Burning),
+            StatusKind::Poison => Some(DamageKind::Poison),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    pub stacks: u32,
+    pub timer: Timer,
+}
+
+impl StatusEffect {
+    /// `stacks` of `kind` that wear off after `remaining` seconds, e.g. from a save
+    pub fn with_remaining(kind: StatusKind, stacks: u32, remaining: f32) -> Self {
+        let mut timer = Timer::from_seconds(kind.duration(), false);
+        timer.set_elapsed(Duration::from_secs_f32(
+            (kind.duration() - remaining).max(0.),
+        ));
+        Self {
+            kind,
+            stacks: stacks.min(kind.max_stacks()),
+            timer,
+        }
+    }
+
+    /// Seconds until it wears off
+    pub fn remaining(&self) -> f32 {
+        self.timer
+            .duration()
+            .saturating_sub(self.timer.elapsed())
+            .as_secs_f32()
+    }
+}
+
+#[derive(Component)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+    tick_timer: Timer,
+}
+
+impl Default for StatusEffects {
+    fn default() -> Self {
+        Self {
+            effects: Vec::new(),
+            tick_timer: Timer::from_seconds(TICK_INTERVAL, true),
+        }
+    }
+}
+
+impl StatusEffects {
+    /// Adds a stack of `kind`, refreshing its duration
+    pub fn apply(&mut self, kind: StatusKind) {
+        if self.has(StatusKind::Freeze) && kind == StatusKind::Slow {
+            return;
+        }
+        if let Some(effect) = self.effects.iter_mut().find(|effect| effect.kind == kind) {
+            effect.stacks = (effect.stacks + 1).min(kind.max_stacks());
+            effect.timer.reset();
+        } else {
+            self.effects.push(StatusEffect {
+                kind,
+                stacks: 1,
+                timer: Timer::from_seconds(kind.duration(), false),
+            });
+        }
+
+        // fully stacked slows freeze the target solid
+        if self
+            .effects
+            .iter()
+            .any(|effect| effect.kind == StatusKind::Slow && effect.stacks == StatusKind::Slow.max_stacks())
+        {
+            self.effects.retain(|effect| effect.kind != StatusKind::Slow);
+            self.apply(StatusKind::Freeze);
+        }
+    }
+
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.effects.iter().any(|effect| effect.kind == kind)
+    }
+
+    /// Multiplier applied to the movement speed of the affected entity
+    pub fn speed_multiplier(&self) -> f32 {
+        self.effects
+            .iter()
+            .map(|effect| effect.kind.speed_multiplier(effect.stacks))
+            .product()
+    }
+
+    /// Color the active effects multiply onto the sprite
+    pub fn tint(&self) -> Color {
+        self.effects.iter().fold(Color::WHITE, |color, effect| {
+            let tint = effect.kind.tint();
+            Color::rgba(
+                color.r() * tint.r(),
+                color.g() * tint.g(),
+                color.b() * tint.b(),
+                color.a(),
+            )
+        })
+    }
+}
+
+pub struct StatusPlugin;
+
+impl Plugin for StatusPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_tick_system_set(
+            TickSubstage::Gameplay,
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::tick_status_effects)
+                .into(),
+        )
+        .add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Playing)
+                .with_system(Self::tint)
+                .into(),
+        );
+    }
+}
+
+impl StatusPlugin {
+    fn tick_status_effects(
+        mut status_query: Query<(Entity, &mut StatusEffects, &Transform)>,
+        mut damage_events: EventWriter<DamageEvent>,
+    ) {
+        for (entity, mut status_effects, transform) in status_query.iter_mut() {
+            if status_effects.effects.is_empty() {
+                continue;
+            }
+
+            status_effects.tick_timer.tick(tick_delta());
+            if status_effects.tick_timer.just_finished() {
+                for effect in status_effects.effects.iter() {
+                    if let Some(kind) = effect.kind.damage_kind() {
+                        damage_events.send(DamageEvent {
+                            target: entity,
+                            position: transform.translation,
+                            amount: effect.kind.damage_per_second()
+                                * effect.stacks as f32
+                                * TICK_INTERVAL,
+                            kind,
+                            crit: false,
+                            status: None,
+                        });
+                    }
+                }
+            }
+
+            for effect in status_effects.effects.iter_mut() {
+                effect.timer.tick(tick_delta());
+            }
+            status_effects
+                .effects
+                .retain(|effect| !effect.timer.finished());
+        }
+    }
+
+    /// Hit flashes take priority over status tints
+    fn tint(
+        mut sprite_query: Query<(&StatusEffects, &mut Sprite, Option<&FlashingTimer>)>,
+        mut atlas_sprite_query: Query<(&StatusEffects, &mut TextureAtlasSprite, Option<&FlashingTimer>)>,
+    ) {
+        let color = |status_effects: &StatusEffects, flashing: Option<&FlashingTimer>| match flashing {
+            Some(flashing) if !flashing.timer.finished() => Color::rgba(255., 255., 255., 1.),
+            _ => status_effects.tint(),
+        };
+        for (status_effects, mut sprite, flashing) in sprite_query.iter_mut() {
+            sprite.color = color(status_effects, flashing);
+        }
+        // enemies, drawn from a sprite sheet
+        for (status_effects, mut sprite, flashing) in atlas_sprite_query.iter_mut() {
+            sprite.color = color(status_effects, flashing);
+        }
+    }
+}
@@ -6,9 +6,26 @@ use bevy_ecs_ldtk::prelude::*;
 use bevy_rapier2d::prelude::*;
 use iyes_loopless::prelude::ConditionSet;
 
-use crate::{GameState, RESOLUTION};
+use crate::{GameState, MyAssets, RESOLUTION};
 
-use super::player::Player;
+use super::{
+    damage::{DamageEvent, DamageKind},
+    display::{FitCamera, ViewScaling},
+    enemy::Enemy,
+    player::Player,
+    input::PlayerInput,
+    settings::Settings,
+    timestep::{AppGameTickExt, InterpolateTransforms, TickSubstage, TIMESTEP},
+    ColliderBundle,
+};
+
+/// Damage per second dealt to anything touching a hazard tile
+const HAZARD_DPS: f32 = 2.;
+/// Damage the player takes for falling into a pit
+const PIT_DAMAGE: f32 = 3.;
+/// Movement speed multiplier while wading through water
+pub const WATER_SPEED: f32 = 0.5;
+const DESTRUCTIBLE_HP: f32 = 15.;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
 pub struct Wall;
@@ -21,26 +38,452 @@ pub struct WallBundle {
     wall: Wall,
 }
 
+/// Hurts anything touching it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Hazard;
+
+/// Slows down anything wading through it
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Water;
+
+/// Kills enemies and throws the player back to safe ground
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Pit;
+
+/// A wall that can be shot or slashed down
+///
+/// It's merged into the level's wall colliders like any other wall.
+#[derive(Copy, Clone, PartialEq, Debug, Component)]
+pub struct Destructible {
+    pub hp: f32,
+    pub slashed: bool,
+}
+
+impl Default for Destructible {
+    fn default() -> Self {
+        Self {
+            hp: DESTRUCTIBLE_HP,
+            slashed: false,
+        }
+    }
+}
+
+/// Marks a level whose wall colliders are out of date
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct RebuildWallCollision;
+
+#[derive(Clone, Default, Bundle, LdtkIntCell)]
+pub struct HazardBundle {
+    hazard: Hazard,
+    sensor: Sensor,
+    #[from_int_grid_cell]
+    #[bundle]
+    collider_bundle: ColliderBundle,
+}
+
+#[derive(Clone, Default, Bundle, LdtkIntCell)]
+pub struct WaterBundle {
+    water: Water,
+    sensor: Sensor,
+    #[from_int_grid_cell]
+    #[bundle]
+    collider_bundle: ColliderBundle,
+}
+
+#[derive(Clone, Default, Bundle, LdtkIntCell)]
+pub struct PitBundle {
+    pit: Pit,
+    sensor: Sensor,
+    #[from_int_grid_cell]
+    #[bundle]
+    collider_bundle: ColliderBundle,
+}
+
+#[derive(Clone, Default, Bundle, LdtkIntCell)]
+pub struct DestructibleBundle {
+    destructible: Destructible,
+}
+
+/// How the camera follows the player
+pub struct CameraFollow {
+    /// roughly how long the camera takes to catch up, 0 snaps straight to the target
+    pub smooth_time: f32,
+    /// half size of the box around the camera's focus the player can move in without moving it
+    pub dead_zone: Vec2,
+    /// fraction of the distance to the cursor the camera leans towards it
+    pub look_ahead: f32,
+    pub max_look_ahead: f32,
+}
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        Self {
+            smooth_time: 0.15,
+            dead_zone: Vec2::new(24., 16.),
+            look_ahead: 0.25,
+            max_look_ahead: 48.,
+        }
+    }
+}
+
+/// Where a camera is headed and how fast it's getting there
+#[derive(Clone, Copy, Debug, Default, Component)]
+pub struct CameraFocus {
+    /// point the dead-zone is centered on
+    focus: Vec2,
+    /// smoothed point the camera is actually centered on
+    position: Vec2,
+    velocity: Vec2,
+    initialized: bool,
+}
+
+/// Critically damped spring from `current` towards `target`
+fn smooth_damp(current: Vec2, target: Vec2, velocity: &mut Vec2, smooth_time: f32, dt: f32) -> Vec2 {
+    if smooth_time <= 0. {
+        *velocity = Vec2::ZERO;
+        return target;
+    }
+    let omega = 2. / smooth_time;
+    let x = omega * dt;
+    // cheap approximation of e^-x that stays stable for large time steps
+    let decay = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+    let offset = current - target;
+    let temp = (*velocity + omega * offset) * dt;
+    *velocity = (*velocity - omega * temp) * decay;
+    target + (offset + temp) * decay
+}
+
+/// Marks something that is currently in water
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Component)]
+pub struct Wading;
+
+/// Where the player last stood safely, to be put back there after falling into a pit
+#[derive(Copy, Clone, PartialEq, Debug, Default, Component)]
+pub struct SafeGround(pub Vec3);
+
+/// The destructible tile closest to `position`, if any is within `reach` of its edge
+pub fn nearest_destructible<'a>(
+    position: Vec2,
+    reach: f32,
+    tiles: impl Iterator<Item = (Entity, &'a GlobalTransform)>,
+) -> Option<Entity> {
+    tiles
+        .map(|(tile, transform)| {
+            let offset = (position - transform.translation.truncate()).abs() - Vec2::splat(8.);
+            (tile, offset.max(Vec2::ZERO).length())
+        })
+        .filter(|(_, distance)| *distance <= reach)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(tile, _)| tile)
+}
+
+/// Everything intersecting any of `tiles`
+fn touching(
+    tiles: impl Iterator<Item = Entity>,
+    rapier_context: &RapierContext,
+) -> HashSet<Entity> {
+    tiles
+        .flat_map(|tile| {
+            rapier_context
+                .intersections_with(tile)
+                .filter(|(_, _, intersecting)| *intersecting)
+                .map(move |(a, b, _)| if a == tile { b } else { a })
+        })
+        .collect()
+}
+
+/// Represents a wide wall that is 1 tile tall
+/// Used to spawn wall collisions
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
+struct Plate {
+    left: i32,
+    right: i32,
+}
+
+/// Merges wall tiles into rectangles, for spawning as few colliders as possible
+///
+/// Every wall is covered by exactly one rectangle. Bounds are inclusive grid coordinates.
+pub fn merge_wall_rects(walls: &HashSet<GridCoords>) -> Vec<Rect<i32>> {
+    if walls.is_empty() {
+        return Vec::new();
+    }
+    let min_x = walls.iter().map(|c| c.x).min().unwrap();
+    let max_x = walls.iter().map(|c| c.x).max().unwrap();
+    let min_y = walls.iter().map(|c| c.y).min().unwrap();
+    let max_y = walls.iter().map(|c| c.y).max().unwrap();
+
+    // combine wall tiles into flat "plates" in each individual row
+    let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
+
+    for y in min_y..=max_y {
+        let mut row_plates: Vec<Plate> = Vec::new();
+        let mut plate_start = None;
+
+        // + 1 to the width so the algorithm "terminates" plates that touch the right
+        // edge
+        for x in min_x..=max_x + 1 {
+            match (plate_start, walls.contains(&GridCoords { x, y })) {
+                (Some(s), false) => {
+                    row_plates.push(Plate {
+                        left: s,
+                        right: x - 1,
+                    });
+                    plate_start = None;
+                }
+                (None, true) => plate_start = Some(x),
+                _ => (),
+            }
+        }
+
+        plate_stack.push(row_plates);
+    }
+
+    // combine "plates" into rectangles across multiple rows
+    let mut wall_rects: Vec<Rect<i32>> = Vec::new();
+    let mut previous_rects: HashMap<Plate, Rect<i32>> = HashMap::new();
+
+    // an extra empty row so the algorithm "terminates" the rects that touch the top
+    // edge
+    plate_stack.push(Vec::new());
+
+    for (y, row) in (min_y..).zip(plate_stack.iter()) {
+        let mut current_rects: HashMap<Plate, Rect<i32>> = HashMap::new();
+        for plate in row {
+            if let Some(previous_rect) = previous_rects.remove(plate) {
+                current_rects.insert(
+                    *plate,
+                    Rect {
+                        top: previous_rect.top + 1,
+                        ..previous_rect
+                    },
+                );
+            } else {
+                current_rects.insert(
+                    *plate,
+                    Rect {
+                        bottom: y,
+                        top: y,
+                        left: plate.left,
+                        right: plate.right,
+                    },
+                );
+            }
+        }
+
+        // Any plates that weren't removed above have terminated
+        wall_rects.append(&mut previous_rects.values().copied().collect());
+        previous_rects = current_rects;
+    }
+
+    wall_rects
+}
+
 pub struct TileMapPlugin;
 
 impl Plugin for TileMapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
-            ConditionSet::new()
-                .run_in_state(GameState::Playing)
-                .with_system(Self::camera_fit_inside_current_level)
-                .with_system(Self::spawn_wall_collision)
-                .into(),
-        );
+        app.init_resource::<CameraFollow>()
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    // follows where the player is drawn, not where the last tick left it
+                    .after(InterpolateTransforms)
+                    .label(FitCamera)
+                    .with_system(Self::camera_fit_inside_current_level)
+                    .into(),
+            )
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::update_level_selection)
+                    .with_system(Self::spawn_wall_collision)
+                    .with_system(Self::color_tiles)
+                    .into(),
+            )
+            .add_tick_system_set(
+                TickSubstage::Gameplay,
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::hazard_damage)
+                    .with_system(Self::wade)
+                    .with_system(Self::fall_into_pits)
+                    .into(),
+            )
+            .add_tick_system_set(
+                TickSubstage::Damage,
+                ConditionSet::new()
+                    .run_in_state(GameState::Playing)
+                    .with_system(Self::break_destructibles)
+                    .into(),
+            );
     }
 }
 
 impl TileMapPlugin {
+    /// The Collisions layer only has auto-tiles for walls, so the other tiles are drawn here
+    fn color_tiles(
+        mut commands: Commands,
+        tile_query: Query<(Entity, &Transform, &IntGridCell), Added<IntGridCell>>,
+        my_assets: Res<MyAssets>,
+    ) {
+        for (entity, transform, cell) in tile_query.iter() {
+            let color = match cell.value {
+                2 => Color::rgb(0.85, 0.25, 0.2),
+                3 => Color::rgba(0.2, 0.45, 0.85, 0.8),
+                4 => Color::rgb(0.08, 0.08, 0.1),
+                5 => Color::rgb(0.55, 0.35, 0.2),
+                _ => continue,
+            };
+            commands.entity(entity).insert_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::splat(16.)),
+                    ..Default::default()
+                },
+                texture: my_assets.wall.clone(),
+                transform: *transform,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Makes whichever level the player walked into the current one
+    ///
+    /// Its neighbours get loaded and everything else unloaded by `LdtkPlugin`.
+    fn update_level_selection(
+        level_query: Query<(&Handle<LdtkLevel>, &Transform), Without<Player>>,
+        player_query: Query<&Transform, With<Player>>,
+        mut level_selection: ResMut<LevelSelection>,
+        ldtk_levels: Res<Assets<LdtkLevel>>,
+    ) {
+        for (level_handle, level_transform) in level_query.iter() {
+            if let Some(ldtk_level) = ldtk_levels.get(level_handle) {
+                let level_bounds = Rect {
+                    left: level_transform.translation.x,
+                    right: level_transform.translation.x + ldtk_level.level.px_wid as f32,
+                    bottom: level_transform.translation.y,
+                    top: level_transform.translation.y + ldtk_level.level.px_hei as f32,
+                };
+
+                for player_transform in player_query.iter() {
+                    if player_transform.translation.x < level_bounds.right
+                        && player_transform.translation.x > level_bounds.left
+                        && player_transform.translation.y < level_bounds.top
+                        && player_transform.translation.y > level_bounds.bottom
+                        && !level_selection.is_match(&0, &ldtk_level.level)
+                    {
+                        *level_selection = LevelSelection::Uid(ldtk_level.level.uid);
+                    }
+                }
+            }
+        }
+    }
+
+    fn hazard_damage(
+        hazard_query: Query<Entity, With<Hazard>>,
+        mut player_query: Query<(Entity, &mut Player)>,
+        enemy_query: Query<(Entity, &Transform), With<Enemy>>,
+        rapier_context: Res<RapierContext>,
+        mut damage_events: EventWriter<DamageEvent>,
+        settings: Res<Settings>,
+    ) {
+        // standing on several hazard tiles at once doesn't hurt more
+        let burned = touching(hazard_query.iter(), &rapier_context);
+
+        for (player_e, mut player) in player_query.iter_mut() {
+            if burned.contains(&player_e) {
+                player.hp -= HAZARD_DPS * settings.difficulty.damage_taken() * TIMESTEP;
+            }
+        }
+        for (enemy_e, enemy_transform) in enemy_query.iter() {
+            if burned.contains(&enemy_e) {
+                damage_events.send(DamageEvent {
+                    target: enemy_e,
+                    position: enemy_transform.translation,
+                    amount: HAZARD_DPS * TIMESTEP,
+                    kind: DamageKind::Hazard,
+                    crit: false,
+                    status: None,
+                });
+            }
+        }
+    }
+
+    fn wade(
+        mut commands: Commands,
+        water_query: Query<Entity, With<Water>>,
+        wader_query: Query<(Entity, Option<&Wading>), Or<(With<Player>, With<Enemy>)>>,
+        rapier_context: Res<RapierContext>,
+    ) {
+        let in_water = touching(water_query.iter(), &rapier_context);
+
+        for (entity, wading) in wader_query.iter() {
+            match (in_water.contains(&entity), wading.is_some()) {
+                (true, false) => {
+                    commands.entity(entity).insert(Wading);
+                }
+                (false, true) => {
+                    commands.entity(entity).remove::<Wading>();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn fall_into_pits(
+        pit_query: Query<(), With<Pit>>,
+        hazard_query: Query<Entity, With<Hazard>>,
+        mut player_query: Query<(Entity, &mut Player, &mut Transform, &mut Velocity, &mut SafeGround)>,
+        enemy_query: Query<(Entity, &Enemy, &Transform), Without<Player>>,
+        rapier_context: Res<RapierContext>,
+        mut damage_events: EventWriter<DamageEvent>,
+        settings: Res<Settings>,
+    ) {
+        // only falls in once its center is over the pit, so brushing past the edge is safe
+        let over_pit = |position: Vec3| {
+            let mut falling = false;
+            rapier_context.intersections_with_point(
+                position.truncate(),
+                QueryFilter::default(),
+                |entity| {
+                    falling = pit_query.contains(entity);
+                    !falling
+                },
+            );
+            falling
+        };
+
+        for (player_e, mut player, mut transform, mut velocity, mut safe_ground) in
+            player_query.iter_mut()
+        {
+            if over_pit(transform.translation) {
+                player.hp -= PIT_DAMAGE * settings.difficulty.damage_taken();
+                transform.translation = safe_ground.0;
+                velocity.linvel = Vec2::ZERO;
+            } else if !touching(hazard_query.iter(), &rapier_context).contains(&player_e) {
+                safe_ground.0 = transform.translation;
+            }
+        }
+        for (enemy_e, enemy, enemy_transform) in enemy_query.iter() {
+            if over_pit(enemy_transform.translation) {
+                damage_events.send(DamageEvent {
+                    target: enemy_e,
+                    position: enemy_transform.translation,
+                    amount: enemy.hp,
+                    kind: DamageKind::Hazard,
+                    crit: false,
+                    status: None,
+                });
+            }
+        }
+    }
+
     fn camera_fit_inside_current_level(
         mut camera_query: Query<
             (
                 &mut bevy::render::camera::OrthographicProjection,
                 &mut Transform,
+                &mut CameraFocus,
             ),
             Without<Player>,
         >,
@@ -51,15 +494,54 @@ impl TileMapPlugin {
         >,
         level_selection: Res<LevelSelection>,
         ldtk_levels: Res<Assets<LdtkLevel>>,
+        follow: Res<CameraFollow>,
+        input: Res<PlayerInput>,
+        time: Res<Time>,
+        windows: Res<Windows>,
+        view_scaling: Res<ViewScaling>,
     ) {
+        // there's no player while the level loads or after they die, and nothing to follow
         if let Ok(Transform {
             translation: player_translation,
             ..
         }) = player_query.get_single()
         {
-            let player_translation = *player_translation;
+            let player_translation = player_translation.truncate();
+
+            let (mut orthographic_projection, mut camera_transform, mut camera_focus) =
+                camera_query.single_mut();
+
+            if !camera_focus.initialized {
+                camera_focus.focus = player_translation;
+                camera_focus.position = player_translation;
+                camera_focus.initialized = true;
+            }
 
-            let (mut orthographic_projection, mut camera_transform) = camera_query.single_mut();
+            // the focus only moves once the player pushes against the edge of the dead-zone
+            let offset = player_translation - camera_focus.focus;
+            camera_focus.focus += offset - offset.clamp(-follow.dead_zone, follow.dead_zone);
+
+            let look_ahead = match input.cursor {
+                Some(cursor) => ((cursor - player_translation) * follow.look_ahead)
+                    .clamp_length_max(follow.max_look_ahead),
+                None => Vec2::ZERO,
+            };
+            let target = camera_focus.focus + look_ahead;
+
+            let CameraFocus {
+                position,
+                mut velocity,
+                ..
+            } = *camera_focus;
+            camera_focus.position = smooth_damp(
+                position,
+                target,
+                &mut velocity,
+                follow.smooth_time,
+                time.delta_seconds(),
+            );
+            camera_focus.velocity = velocity;
+            let focus = camera_focus.position;
 
             for (level_transform, level_handle) in level_query.iter() {
                 if let Some(ldtk_level) = ldtk_levels.get(level_handle) {
@@ -76,7 +558,7 @@ impl TileMapPlugin {
                             orthographic_projection.top = (level.px_hei as f32 / 9.).round() * 9.;
                             orthographic_projection.right =
                                 orthographic_projection.top * RESOLUTION;
-                            camera_transform.translation.x = (player_translation.x
+                            camera_transform.translation.x = (focus.x
                                 - level_transform.translation.x
                                 - orthographic_projection.right / 2.)
                                 .clamp(0., level.px_wid as f32 - orthographic_projection.right);
@@ -87,7 +569,7 @@ impl TileMapPlugin {
                                 (level.px_wid as f32 / 16.).round() * 16.;
                             orthographic_projection.top =
                                 orthographic_projection.right / RESOLUTION;
-                            camera_transform.translation.y = (player_translation.y
+                            camera_transform.translation.y = (focus.y
                                 - level_transform.translation.y
                                 - orthographic_projection.top / 2.)
                                 .clamp(0., level.px_hei as f32 - orthographic_projection.top);
@@ -96,27 +578,80 @@ impl TileMapPlugin {
 
                         camera_transform.translation.x += level_transform.translation.x;
                         camera_transform.translation.y += level_transform.translation.y;
+
+                        // the view stays at 0..right, 0..top and the rest of the window goes
+                        // around it, so a window of any shape shows the game undistorted
+                        let view = Vec2::new(orthographic_projection.right, orthographic_projection.top);
+                        if let Some(window) = windows.get_primary() {
+                            let window_size = Vec2::new(
+                                window.physical_width() as f32,
+                                window.physical_height() as f32,
+                            );
+                            // minimized
+                            if window_size.min_element() > 0. {
+                                let margin = (view_scaling.visible_size(view, window_size) - view) / 2.;
+                                orthographic_projection.left = -margin.x;
+                                orthographic_projection.right = view.x + margin.x;
+                                orthographic_projection.bottom = -margin.y;
+                                orthographic_projection.top = view.y + margin.y;
+                            }
+                        }
+                        if *view_scaling == ViewScaling::PixelPerfect {
+                            camera_transform.translation.x = camera_transform.translation.x.round();
+                            camera_transform.translation.y = camera_transform.translation.y.round();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn break_destructibles(
+        mut commands: Commands,
+        mut damage_events: EventReader<DamageEvent>,
+        mut destructible_query: Query<(&mut Destructible, &Parent)>,
+        parent_query: Query<&Parent, Without<Destructible>>,
+    ) {
+        for event in damage_events.iter() {
+            if let Ok((mut destructible, &Parent(chunk))) = destructible_query.get_mut(event.target) {
+                // already broken by an earlier hit this tick
+                if destructible.hp <= 0. {
+                    continue;
+                }
+                destructible.hp -= event.amount;
+                if destructible.hp <= 0. {
+                    // the tilemap never draws these tiles, so despawning is all it takes to remove one
+                    commands.entity(event.target).despawn_recursive();
+                    if let Ok(&Parent(level_entity)) = parent_query.get(chunk) {
+                        commands.entity(level_entity).insert(RebuildWallCollision);
                     }
                 }
             }
-        } else {
-            dbg!("couldn't find player");
         }
     }
 
     fn spawn_wall_collision(
         mut commands: Commands,
-        wall_query: Query<(&GridCoords, &Parent), Added<Wall>>,
-        parent_query: Query<&Parent, Without<Wall>>,
+        added_query: Query<&Parent, Or<(Added<Wall>, Added<Destructible>)>>,
+        wall_query: Query<(&GridCoords, &Parent), Or<(With<Wall>, With<Destructible>)>>,
+        parent_query: Query<&Parent, (Without<Wall>, Without<Destructible>)>,
+        rebuild_query: Query<Entity, With<RebuildWallCollision>>,
+        collision_query: Query<(Entity, &Parent), With<WallCollision>>,
         level_query: Query<(Entity, &Handle<LdtkLevel>)>,
         levels: Res<Assets<LdtkLevel>>,
     ) {
-        /// Represents a wide wall that is 1 tile tall
-        /// Used to spawn wall collisions
-        #[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Hash)]
-        struct Plate {
-            left: i32,
-            right: i32,
+        // only levels that gained or lost walls get their colliders rebuilt
+        let mut dirty_levels: HashSet<Entity> = rebuild_query.iter().collect();
+        added_query.for_each(|&Parent(parent)| {
+            // the intgrid tiles' direct parents will be bevy_ecs_tilemap chunks, not the level
+            // To get the level, you need their grandparents, which is where parent_query comes in
+            if let Ok(&Parent(level_entity)) = parent_query.get(parent) {
+                dirty_levels.insert(level_entity);
+            }
+        });
+
+        if dirty_levels.is_empty() {
+            return;
         }
 
         // consider where the walls are
@@ -124,97 +659,37 @@ impl TileMapPlugin {
         let mut level_to_wall_locations: HashMap<Entity, HashSet<GridCoords>> = HashMap::new();
 
         wall_query.for_each(|(&grid_coords, &Parent(parent))| {
-            // the intgrid tiles' direct parents will be bevy_ecs_tilemap chunks, not the level
-            // To get the level, you need their grandparents, which is where parent_query comes in
             if let Ok(&Parent(level_entity)) = parent_query.get(parent) {
-                level_to_wall_locations
-                    .entry(level_entity)
-                    .or_insert(HashSet::new())
-                    .insert(grid_coords);
+                if dirty_levels.contains(&level_entity) {
+                    level_to_wall_locations
+                        .entry(level_entity)
+                        .or_insert(HashSet::new())
+                        .insert(grid_coords);
+                }
             }
         });
 
-        if !wall_query.is_empty() {
-            level_query.for_each(|(level_entity, level_handle)| {
+        for (collision_e, &Parent(level_entity)) in collision_query.iter() {
+            if dirty_levels.contains(&level_entity) {
+                commands.entity(collision_e).despawn_recursive();
+            }
+        }
+
+        level_query.for_each(|(level_entity, level_handle)| {
+            if dirty_levels.contains(&level_entity) {
+                commands.entity(level_entity).remove::<RebuildWallCollision>();
+
                 if let Some(level_walls) = level_to_wall_locations.get(&level_entity) {
                     let level = levels
                         .get(level_handle)
                         .expect("Level should be loaded by this point");
 
-                    let LayerInstance {
-                        c_wid: width,
-                        c_hei: height,
-                        grid_size,
-                        ..
-                    } = level
+                    let LayerInstance { grid_size, .. } = level
                         .level
                         .layer_instances
                         .clone()
                         .expect("Level asset should have layers")[0];
-                    dbg!(width, height);
-                    // combine wall tiles into flat "plates" in each individual row
-                    let mut plate_stack: Vec<Vec<Plate>> = Vec::new();
-
-                    for y in 0..height {
-                        let mut row_plates: Vec<Plate> = Vec::new();
-                        let mut plate_start = None;
-
-                        // + 1 to the width so the algorithm "terminates" plates that touch the right
-                        // edge
-                        for x in 0..width + 1 {
-                            match (plate_start, level_walls.contains(&GridCoords { x, y })) {
-                                (Some(s), false) => {
-                                    row_plates.push(Plate {
-                                        left: s,
-                                        right: x - 1,
-                                    });
-                                    plate_start = None;
-                                }
-                                (None, true) => plate_start = Some(x),
-                                _ => (),
-                            }
-                        }
-
-                        plate_stack.push(row_plates);
-                    }
-
-                    // combine "plates" into rectangles across multiple rows
-                    let mut wall_rects: Vec<Rect<i32>> = Vec::new();
-                    let mut previous_rects: HashMap<Plate, Rect<i32>> = HashMap::new();
-
-                    // an extra empty row so the algorithm "terminates" the rects that touch the top
-                    // edge
-                    plate_stack.push(Vec::new());
-
-                    for (y, row) in plate_stack.iter().enumerate() {
-                        let mut current_rects: HashMap<Plate, Rect<i32>> = HashMap::new();
-                        for plate in row {
-                            if let Some(previous_rect) = previous_rects.remove(plate) {
-                                current_rects.insert(
-                                    *plate,
-                                    Rect {
-                                        top: previous_rect.top + 1,
-                                        ..previous_rect
-                                    },
-                                );
-                            } else {
-                                // dbg!(y, plate.left, plate.right);
-                                current_rects.insert(
-                                    *plate,
-                                    Rect {
-                                        bottom: y as i32,
-                                        top: y as i32,
-                                        left: plate.left,
-                                        right: plate.right,
-                                    },
-                                );
-                            }
-                        }
-
-                        // Any plates that weren't removed above have terminated
-                        wall_rects.append(&mut previous_rects.values().copied().collect());
-                        previous_rects = current_rects;
-                    }
+                    let wall_rects = merge_wall_rects(level_walls);
 
                     // spawn colliders for every rectangle
                     for wall_rect in wall_rects {
@@ -254,7 +729,7 @@ impl TileMapPlugin {
                         });
                     }
                 }
-            });
-        }
+            }
+        });
     }
 }
@@ -0,0 +1,249 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+/// Length of a single gameplay tick in seconds
+pub const TIMESTEP: f32 = 1. / 60.;
+/// Stops a slow frame from queueing up more ticks than it can handle
+const MAX_TICKS_PER_FRAME: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct GameTickStage;
+
+/// Systems reading the rendered position of interpolated entities go after this
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub struct InterpolateTransforms;
+
+/// Sub-stages every tick runs through, in order
+#[derive(Debug, Clone, Copy)]
+pub enum TickSubstage {
+    /// Puts interpolated transforms back where the simulation left them,
+    /// global ones included so rapier doesn't sync bodies to the blended position
+    First,
+    /// Consumes the input for this tick
+    Input,
+    Gameplay,
+    /// Applies the `DamageEvent`s sent during `Gameplay`, so none are read before they're sent
+    Damage,
+    PhysicsSync,
+    PhysicsStep,
+    PhysicsWriteback,
+    /// Records where the simulation left everything
+    Last,
+}
+
+/// A tick's worth of time, for systems that run in the `GameTickStage`
+pub fn tick_delta() -> Duration {
+    Duration::from_secs_f32(TIMESTEP)
+}
+
+/// Smooths out the movement of a simulated entity between ticks
+///
+/// Its `Transform` is only correct during a tick; between ticks it's blended
+/// between the last two simulated positions. Interpolated entities have no parent,
+/// since their `GlobalTransform` is put back from their `Transform` alone.
+#[derive(Component, Default)]
+pub struct InterpolatedTransform {
+    previous: Vec3,
+    current: Vec3,
+    initialized: bool,
+}
+
+impl InterpolatedTransform {
+    /// Jumps straight to `translation` instead of blending towards it from where it was
+    pub fn teleport(&mut self, translation: Vec3) {
+        self.previous = translation;
+        self.current = translation;
+        self.initialized = true;
+    }
+}
+
+/// Bookkeeping for the fixed timestep, readable by any system
+#[derive(Default)]
+pub struct GameTick {
+    /// number of ticks run since startup
+    pub tick: u64,
+    /// fraction of a tick left in the accumulator after the last frame
+    pub overstep: f32,
+    /// run exactly one tick per frame instead of following real time
+    pub manual: bool,
+    /// run no ticks at all, e.g. while a menu is open
+    pub paused: bool,
+}
+
+/// Runs its sub-stages zero or more times per frame so every run of them
+/// advances the game by exactly `TIMESTEP`
+pub struct FixedTimestepStage {
+    step: Duration,
+    accumulator: Duration,
+    substages: Vec<SystemStage>,
+}
+
+impl FixedTimestepStage {
+    fn new(step: Duration) -> Self {
+        Self {
+            step,
+            accumulator: Duration::ZERO,
+            substages: (0..=TickSubstage::Last as usize)
+                .map(|_| SystemStage::parallel())
+                .collect(),
+        }
+    }
+
+    pub fn substage(&mut self, substage: TickSubstage) -> &mut SystemStage {
+        &mut self.substages[substage as usize]
+    }
+
+    /// Runs a single tick regardless of how much time has passed
+    pub fn run_tick(&mut self, world: &mut World) {
+        for substage in self.substages.iter_mut() {
+            substage.run(world);
+        }
+        world.resource_mut::<GameTick>().tick += 1;
+    }
+}
+
+impl Stage for FixedTimestepStage {
+    fn run(&mut self, world: &mut World) {
+        // time spent paused isn't made up for afterwards
+        if world.resource::<GameTick>().paused {
+            return;
+        }
+        if world.resource::<GameTick>().manual {
+            self.run_tick(world);
+            return;
+        }
+
+        self.accumulator += world.resource::<Time>().delta();
+
+        let mut ticks = 0;
+        while self.accumulator >= self.step {
+            if ticks == MAX_TICKS_PER_FRAME {
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+            self.accumulator -= self.step;
+            self.run_tick(world);
+            ticks += 1;
+        }
+
+        world.resource_mut::<GameTick>().overstep =
+            self.accumulator.as_secs_f32() / self.step.as_secs_f32();
+    }
+}
+
+pub trait AppGameTickExt {
+    /// Adds a system set that runs once per gameplay tick instead of once per frame
+    fn add_tick_system_set(&mut self, substage: TickSubstage, system_set: SystemSet) -> &mut Self;
+}
+
+impl AppGameTickExt for App {
+    fn add_tick_system_set(&mut self, substage: TickSubstage, system_set: SystemSet) -> &mut Self {
+        self.stage(GameTickStage, |stage: &mut FixedTimestepStage| {
+            stage.substage(substage).add_system_set(system_set);
+            stage
+        })
+    }
+}
+
+pub struct TimestepPlugin;
+
+impl Plugin for TimestepPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameTick>()
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Fixed {
+                    dt: TIMESTEP,
+                    substeps: 1,
+                },
+                ..Default::default()
+            })
+            .add_stage_before(
+                CoreStage::Update,
+                GameTickStage,
+                FixedTimestepStage::new(Duration::from_secs_f32(TIMESTEP)),
+            )
+            // rapier steps once per tick instead of once per frame
+            .add_tick_system_set(
+                TickSubstage::PhysicsSync,
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::SyncBackend),
+            )
+            .add_tick_system_set(
+                TickSubstage::PhysicsStep,
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::StepSimulation),
+            )
+            .add_tick_system_set(
+                TickSubstage::PhysicsWriteback,
+                RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::Writeback),
+            )
+            // removals have to be caught every frame, before they're cleared
+            .add_stage_before(
+                CoreStage::Last,
+                PhysicsStages::DetectDespawn,
+                SystemStage::parallel().with_system_set(
+                    RapierPhysicsPlugin::<NoUserData>::get_systems(PhysicsStages::DetectDespawn),
+                ),
+            )
+            .add_tick_system_set(
+                TickSubstage::First,
+                SystemSet::new().with_system(Self::restore_transforms),
+            )
+            .add_tick_system_set(
+                TickSubstage::Last,
+                SystemSet::new().with_system(Self::record_transforms),
+            )
+            .add_system(Self::interpolate_transforms.label(InterpolateTransforms));
+    }
+}
+
+impl TimestepPlugin {
+    fn restore_transforms(
+        mut query: Query<(
+            &mut Transform,
+            &mut GlobalTransform,
+            &InterpolatedTransform,
+            Option<&Children>,
+        )>,
+        mut child_query: Query<(&Transform, &mut GlobalTransform), Without<InterpolatedTransform>>,
+    ) {
+        for (mut transform, mut global_transform, interpolated, children) in query.iter_mut() {
+            if !interpolated.initialized {
+                continue;
+            }
+            transform.translation = interpolated.current;
+            *global_transform = GlobalTransform::from(*transform);
+            // e.g. the slash, whose collider rapier also syncs from its `GlobalTransform`
+            for &child in children.into_iter().flat_map(|children| children.iter()) {
+                if let Ok((child_transform, mut child_global_transform)) = child_query.get_mut(child) {
+                    *child_global_transform = global_transform.mul_transform(*child_transform);
+                }
+            }
+        }
+    }
+
+    fn record_transforms(mut query: Query<(&Transform, &mut InterpolatedTransform)>) {
+        for (transform, mut interpolated) in query.iter_mut() {
+            interpolated.previous = if interpolated.initialized {
+                interpolated.current
+            } else {
+                transform.translation
+            };
+            interpolated.current = transform.translation;
+            interpolated.initialized = true;
+        }
+    }
+
+    fn interpolate_transforms(
+        mut query: Query<(&mut Transform, &InterpolatedTransform)>,
+        tick: Res<GameTick>,
+    ) {
+        for (mut transform, interpolated) in query.iter_mut() {
+            if interpolated.initialized {
+                transform.translation = interpolated
+                    .previous
+                    .lerp(interpolated.current, tick.overstep);
+            }
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use bevy::prelude::*;
 use std::f32::consts::FRAC_PI_2;
 
-
+/// Converts a position in the window to the world
+///
+/// The camera's projection always spans the whole window, letterbox bars included,
+/// so this holds for a window of any size.
 pub fn to_world_coordinates(
     camera: &Camera,
     camera_transform: &GlobalTransform,
@@ -0,0 +1,84 @@
+use t5hm::plugins::{
+    animation_graph::{AnimationAttributes, Node, NodeId, NodeTree},
+    player::PlayerState,
+};
+
+struct Attributes<'a> {
+    state: &'a str,
+    index: usize,
+}
+
+impl AnimationAttributes for Attributes<'_> {
+    fn attribute(&self, name: &str) -> Option<String> {
+        (name == "PlayerState").then(|| self.state.to_string())
+    }
+
+    fn index(&self, _name: &str) -> usize {
+        self.index
+    }
+}
+
+/// `player.nodetree` as the node editor saved it
+fn player_tree() -> NodeTree {
+    NodeTree::parse(include_str!("../assets/player.nodetree")).unwrap()
+}
+
+#[test]
+fn parses_player_nodetree() {
+    let tree = player_tree();
+    assert_eq!(tree.root, NodeId(1));
+    assert!(matches!(tree.nodes[&tree.root], Node::Match(_)));
+    assert_eq!(tree.nodes.len(), 2);
+}
+
+#[test]
+fn unkeyed_nodes_get_the_ids_they_are_referred_to_by() {
+    // the melee node is written without an id, the switch node refers to it by one
+    let tree = player_tree();
+    assert!(matches!(tree.nodes.get(&NodeId(0x8EA5_2888_68FD_8F3A)), Some(Node::Index(_))));
+}
+
+#[test]
+fn attacking_plays_the_melee_frames_once() {
+    let tree = player_tree();
+    let frame = |index| tree.frame(&Attributes { state: "Attacking", index });
+    assert_eq!(frame(0), Some("slash/aseprite_animation1.png"));
+    assert_eq!(frame(4), Some("slash/aseprite_animation5.png"));
+    // not a loop, so it stays on the last frame
+    assert_eq!(frame(9), Some("slash/aseprite_animation5.png"));
+}
+
+#[test]
+fn missing_nodes_have_no_frame() {
+    let tree = player_tree();
+    assert_eq!(tree.frame(&Attributes { state: "Idle", index: 0 }), None);
+}
+
+#[test]
+fn nodetree_states_have_frames() {
+    let tree = player_tree();
+    let states = [
+        PlayerState::Idle,
+        PlayerState::Walking,
+        PlayerState::Running,
+        PlayerState::Attacking,
+        PlayerState::FallF,
+        PlayerState::FallB,
+        PlayerState::LayingF,
+        PlayerState::LayingB,
+        PlayerState::StandF,
+        PlayerState::StandB,
+    ];
+    for state in states {
+        let name = format!("{:?}", state);
+        let frame = tree.frame(&Attributes { state: &name, index: 0 });
+        // the rest fall back to the `PlayerBody`
+        assert_eq!(frame.is_some(), state.in_nodetree(), "{:?}", state);
+    }
+}
+
+#[test]
+fn reports_where_parsing_failed() {
+    let error = NodeTree::parse("[ IndexNode(name: \"a\" ").unwrap_err();
+    assert_eq!(error.offset, 22);
+}
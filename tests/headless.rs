@@ -0,0 +1,192 @@
+use bevy::prelude::*;
+use t5hm::plugins::{
+    enemy::Enemy,
+    input::{PlayerInput, Replay, ReplayMode},
+    player::{Player, MAX_HP},
+    rng::GameRng,
+    save::{PlayerSave, SaveGame, SavePlugin, SAVE_VERSION},
+    score::Score,
+    timestep::{GameTick, TIMESTEP},
+};
+
+/// Where the enemy placed in the first level of `thm_map.ldtk` spawns
+const MAP_ENEMY: Vec2 = Vec2::new(184., 184.);
+
+fn run(app: &mut App, ticks: usize) {
+    for _ in 0..ticks {
+        app.update();
+    }
+}
+
+/// Runs `frames` frames that don't run a tick, like a frame drawn between two ticks above 60 fps
+fn run_frames_without_tick(app: &mut App, frames: usize) {
+    app.world.resource_mut::<GameTick>().paused = true;
+    for _ in 0..frames {
+        app.update();
+    }
+    app.world.resource_mut::<GameTick>().paused = false;
+}
+
+fn enemy_positions(app: &mut App) -> Vec<Vec2> {
+    let mut query = app
+        .world
+        .query_filtered::<&Transform, (With<Enemy>, Without<Player>)>();
+    query
+        .iter(&app.world)
+        .map(|transform| transform.translation.truncate())
+        .collect()
+}
+
+#[test]
+fn level_spawns_player_and_enemy() {
+    let mut app = t5hm::headless_app(ReplayMode::Live, GameRng::new(0));
+    run(&mut app, 2);
+
+    let mut player_query = app.world.query_filtered::<(), With<Player>>();
+    assert_eq!(player_query.iter(&app.world).count(), 1);
+
+    // it has already started walking towards the player
+    let enemies = enemy_positions(&mut app);
+    assert!(enemies.iter().any(|enemy| enemy.distance(MAP_ENEMY) < 4.));
+}
+
+#[test]
+fn enemies_spawn_over_time() {
+    let mut app = t5hm::headless_app(ReplayMode::Live, GameRng::new(0));
+    run(&mut app, 2);
+    let before = enemy_positions(&mut app).len();
+
+    // the spawner fires every 5 seconds
+    run(&mut app, (5.5 / TIMESTEP) as usize);
+    assert!(enemy_positions(&mut app).len() > before);
+}
+
+#[test]
+fn same_seed_spawns_same_enemies() {
+    let spawn = |seed| {
+        let mut app = t5hm::headless_app(ReplayMode::Live, GameRng::new(seed));
+        run(&mut app, (11. / TIMESTEP) as usize);
+        let mut positions = enemy_positions(&mut app);
+        positions.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+        positions
+    };
+
+    assert_eq!(spawn(42), spawn(42));
+    // the map's own enemy is in the same place either way, the waves aren't
+    assert_ne!(spawn(42), spawn(43));
+}
+
+#[test]
+fn player_walks_a_full_step_every_tick() {
+    let replay = Replay {
+        seed: 0,
+        timestep: TIMESTEP,
+        frames: vec![
+            PlayerInput {
+                left: true,
+                ..Default::default()
+            };
+            40
+        ],
+    };
+    let mut app = t5hm::headless_app(ReplayMode::Playback { replay, frame: 0 }, GameRng::new(0));
+    run(&mut app, 4);
+
+    // nothing's in the way for a few tiles to the left of the map's player
+    let player_x = |app: &mut App| {
+        let mut query = app.world.query_filtered::<&Transform, With<Player>>();
+        query.single(&app.world).translation.x
+    };
+    let start = player_x(&mut app);
+    run(&mut app, 10);
+    let walked = start - player_x(&mut app);
+    let expected = 10. * Player::default().velocity * TIMESTEP;
+    assert!((walked - expected).abs() < 1., "walked {} instead of {}", walked, expected);
+}
+
+#[test]
+fn shooting_damages_enemy() {
+    shoot_map_enemy(1);
+}
+
+/// Shoots the map's enemy, running `frames_per_tick` frames for every tick,
+/// and checks the bullet hurt it or killed it
+fn shoot_map_enemy(frames_per_tick: usize) {
+    let aim = PlayerInput {
+        cursor: Some(MAP_ENEMY),
+        ..Default::default()
+    };
+    let mut frames = vec![PlayerInput {
+        fire: true,
+        fire_pressed: true,
+        ..aim.clone()
+    }];
+    frames.extend(std::iter::repeat(aim).take(30));
+
+    let replay = Replay {
+        seed: 0,
+        timestep: TIMESTEP,
+        frames,
+    };
+    let mut app = t5hm::headless_app(ReplayMode::Playback { replay, frame: 0 }, GameRng::new(0));
+    run(&mut app, 2);
+
+    let mut enemy_query = app.world.query_filtered::<(Entity, &Transform), With<Enemy>>();
+    let (enemy, _) = enemy_query
+        .iter(&app.world)
+        .find(|(_, transform)| transform.translation.truncate().distance(MAP_ENEMY) < 4.)
+        .expect("the map's enemy should have spawned");
+
+    for _ in 0..30 {
+        run(&mut app, 1);
+        run_frames_without_tick(&mut app, frames_per_tick - 1);
+    }
+    match app.world.get::<Enemy>(enemy) {
+        Some(hit) => assert!(hit.hp < Enemy::default().hp, "the enemy wasn't hurt"),
+        None => assert_eq!(app.world.resource::<Score>().kills, 1, "the enemy vanished without a kill"),
+    }
+}
+
+#[test]
+fn damage_lands_with_several_frames_per_tick() {
+    // a hit read before it's sent would be cleared by the frames in between and lost
+    shoot_map_enemy(3);
+}
+
+#[test]
+fn continued_run_resumes_where_it_was_saved() {
+    // a few tiles left of where the map puts the player
+    let position = Vec3::new(152., 120., 2.);
+    let mut save = Some(SaveGame {
+        version: SAVE_VERSION,
+        tick: 0,
+        level_uid: 0,
+        wave: 0,
+        wave_elapsed: 0.,
+        score: Score::default(),
+        rng: GameRng::new(0),
+        player: PlayerSave {
+            hp: MAX_HP,
+            position,
+            safe_ground: position,
+            ..Default::default()
+        },
+        enemies: Vec::new(),
+    });
+
+    let mut app = t5hm::headless_app(ReplayMode::Live, GameRng::new(0));
+    // only here, the other tests shouldn't touch the real save file
+    app.add_plugin(SavePlugin).add_startup_system(
+        move |mut commands: Commands, mut tick: ResMut<GameTick>, mut replay_mode: ResMut<ReplayMode>| {
+            if let Some(save) = save.take() {
+                save.continue_run(&mut commands, &mut tick, &mut replay_mode);
+            }
+        },
+    );
+    run(&mut app, 10);
+
+    let mut query = app.world.query_filtered::<&Transform, With<Player>>();
+    let resumed = query.single(&app.world).translation;
+    assert!(resumed.truncate().distance(position.truncate()) < 1., "resumed at {}", resumed);
+    assert!(enemy_positions(&mut app).is_empty(), "the map's enemies should be replaced by the saved ones");
+}
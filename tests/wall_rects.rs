@@ -0,0 +1,71 @@
+use bevy::{prelude::*, utils::HashSet};
+use bevy_ecs_ldtk::prelude::*;
+use proptest::prelude::*;
+use t5hm::plugins::tilemap::merge_wall_rects;
+
+fn tiles(rect: &Rect<i32>) -> impl Iterator<Item = GridCoords> + '_ {
+    (rect.bottom..=rect.top)
+        .flat_map(move |y| (rect.left..=rect.right).map(move |x| GridCoords { x, y }))
+}
+
+fn area(rect: &Rect<i32>) -> usize {
+    ((rect.right - rect.left + 1) * (rect.top - rect.bottom + 1)) as usize
+}
+
+/// Number of horizontal runs of walls, the most rectangles the merge should ever need
+fn plate_count(walls: &HashSet<GridCoords>) -> usize {
+    walls
+        .iter()
+        .filter(|c| !walls.contains(&GridCoords { x: c.x - 1, y: c.y }))
+        .count()
+}
+
+fn walls() -> impl Strategy<Value = HashSet<GridCoords>> {
+    proptest::collection::hash_set((-4..24i32, -4..16i32), 0..200).prop_map(|coords| {
+        coords
+            .into_iter()
+            .map(|(x, y)| GridCoords { x, y })
+            .collect()
+    })
+}
+
+#[test]
+fn no_walls_no_rects() {
+    assert!(merge_wall_rects(&HashSet::default()).is_empty());
+}
+
+#[test]
+fn solid_block_is_one_rect() {
+    let walls = (0..24)
+        .flat_map(|x| (0..16).map(move |y| GridCoords { x, y }))
+        .collect();
+    let rects = merge_wall_rects(&walls);
+    assert_eq!(rects.len(), 1);
+    assert_eq!(
+        (rects[0].left, rects[0].right, rects[0].bottom, rects[0].top),
+        (0, 23, 0, 15)
+    );
+}
+
+proptest! {
+    #[test]
+    fn rects_cover_exactly_the_walls(walls in walls()) {
+        let rects = merge_wall_rects(&walls);
+        let covered: HashSet<GridCoords> = rects.iter().flat_map(tiles).collect();
+        prop_assert_eq!(covered, walls);
+    }
+
+    #[test]
+    fn rects_do_not_overlap(walls in walls()) {
+        let rects = merge_wall_rects(&walls);
+        // every wall is covered, so any overlap would push the total area past the wall count
+        prop_assert_eq!(rects.iter().map(area).sum::<usize>(), walls.len());
+    }
+
+    #[test]
+    fn rect_count_is_bounded(walls in walls()) {
+        let rects = merge_wall_rects(&walls);
+        prop_assert!(rects.len() <= plate_count(&walls));
+        prop_assert_eq!(rects.is_empty(), walls.is_empty());
+    }
+}